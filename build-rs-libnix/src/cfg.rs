@@ -0,0 +1,211 @@
+// the cfg grammar of rustc, see
+// https://doc.rust-lang.org/rustc/command-line-arguments.html#--cfg-configure-the-compilation-environment
+// https://doc.rust-lang.org/rustc/check-cfg.html
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    // the content between the quotes, escapes are kept as they are
+    Str(String),
+    OpenParen,
+    CloseParen,
+    Comma,
+    Equals,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+pub(crate) fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_ident_start(c) => name != "_" && chars.all(is_ident_continue),
+        _ => false,
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Equals),
+            '"' => {
+                let mut escaped = false;
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '"' if !escaped => {
+                            end = Some(i);
+                            break;
+                        }
+                        '\\' => escaped = !escaped,
+                        _ => escaped = false,
+                    }
+                }
+                match end {
+                    Some(end) => tokens.push(Token::Str(input[start + 1..end].to_string())),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            c if is_ident_start(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_ident_continue(c) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Ident(input[start..end].to_string()));
+            }
+            c => return Err(format!("unexpected character '{c}'")),
+        }
+    }
+    Ok(tokens)
+}
+
+// cargo:rustc-cfg=NAME or cargo:rustc-cfg=NAME="VALUE", VALUE without the quotes
+pub(crate) fn parse_cfg(input: &str) -> Result<(String, Option<String>), String> {
+    match tokenize(input)?.as_slice() {
+        [Token::Ident(name)] if is_ident(name) => Ok((name.clone(), None)),
+        [Token::Ident(name), Token::Equals, Token::Str(value)] if is_ident(name) => {
+            Ok((name.clone(), Some(value.clone())))
+        }
+        [Token::Ident(_), Token::Equals, ..] => {
+            Err("the value has to be a string literal, expected 'NAME=\"VALUE\"'".to_string())
+        }
+        _ => Err("expected 'NAME' or 'NAME=\"VALUE\"'".to_string()),
+    }
+}
+
+// the cfgs rustc rejects in --cfg (explicit_builtin_cfgs_in_flags), some of them only with a
+// value or only with the values rustc sets itself, and feature, which cargo rejects as features
+// are set with CARGO_FEATURE_<NAME>
+pub(crate) fn is_reserved_cfg(name: &str, value: Option<&str>) -> bool {
+    matches!(
+        (name, value),
+        ("feature", _)
+            | (
+                "overflow_checks"
+                    | "debug_assertions"
+                    | "ub_checks"
+                    | "contract_checks"
+                    | "proc_macro"
+                    | "unix"
+                    | "windows"
+                    | "target_thread_local",
+                None,
+            )
+            | (
+                "sanitize"
+                    | "sanitizer_cfi_generalize_pointers"
+                    | "sanitizer_cfi_normalize_integers"
+                    | "fmt_debug"
+                    | "emscripten_wasm_eh"
+                    | "target_abi"
+                    | "target_env"
+                    | "target_vendor"
+                    | "target_has_reliable_f16"
+                    | "target_has_reliable_f16_math"
+                    | "target_has_reliable_f128"
+                    | "target_has_reliable_f128_math",
+                _,
+            )
+            | ("panic", Some("abort" | "unwind" | "immediate-abort"))
+            | (
+                "relocation_model"
+                    | "target_feature"
+                    | "target_arch"
+                    | "target_endian"
+                    | "target_family"
+                    | "target_os"
+                    | "target_pointer_width"
+                    | "target_has_atomic"
+                    | "target_has_atomic_equal_alignment"
+                    | "target_has_atomic_load_store",
+                Some(_),
+            )
+    )
+}
+
+struct Tokens {
+    tokens: std::vec::IntoIter<Token>,
+}
+
+impl Tokens {
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("expected {what}")),
+        }
+    }
+
+    // the items of a list up to and including the closing paren, trailing commas are fine
+    fn list(
+        &mut self,
+        mut item: impl FnMut(&mut Self, Token) -> Result<(), String>,
+    ) -> Result<(), String> {
+        loop {
+            match self.tokens.next() {
+                Some(Token::CloseParen) => return Ok(()),
+                Some(token) => item(self, token)?,
+                None => return Err("expected ')'".to_string()),
+            }
+            match self.tokens.next() {
+                Some(Token::Comma) => {}
+                Some(Token::CloseParen) => return Ok(()),
+                _ => return Err("expected ',' or ')'".to_string()),
+            }
+        }
+    }
+}
+
+// cargo:rustc-check-cfg=cfg(NAME, ..., values("VALUE", ..., none()))
+// cargo:rustc-check-cfg=cfg(any())
+pub(crate) fn validate_check_cfg(input: &str) -> Result<(), String> {
+    let mut tokens = Tokens {
+        tokens: tokenize(input)?.into_iter(),
+    };
+    tokens.expect(Token::Ident("cfg".to_string()), "'cfg('")?;
+    tokens.expect(Token::OpenParen, "'cfg('")?;
+    let mut values_seen = false;
+    tokens.list(|tokens, token| {
+        if values_seen {
+            return Err("values(...) has to be the last argument of cfg(...)".to_string());
+        }
+        match token {
+            Token::Ident(name) if name == "any" => {
+                tokens.expect(Token::OpenParen, "'any()'")?;
+                tokens.expect(Token::CloseParen, "'any()'")
+            }
+            Token::Ident(name) if name == "values" => {
+                values_seen = true;
+                tokens.expect(Token::OpenParen, "'values('")?;
+                tokens.list(|tokens, token| match token {
+                    Token::Str(_) => Ok(()),
+                    Token::Ident(name) if name == "none" || name == "any" => {
+                        tokens.expect(Token::OpenParen, &format!("'{name}()'"))?;
+                        tokens.expect(Token::CloseParen, &format!("'{name}()'"))
+                    }
+                    _ => Err("values(...) takes string literals, none() or any()".to_string()),
+                })
+            }
+            Token::Ident(name) if is_ident(&name) => Ok(()),
+            _ => Err("cfg(...) takes names and values(...)".to_string()),
+        }
+    })?;
+    match tokens.tokens.next() {
+        None => Ok(()),
+        Some(_) => Err("unexpected input after cfg(...)".to_string()),
+    }
+}
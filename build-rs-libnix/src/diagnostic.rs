@@ -0,0 +1,134 @@
+use crate::error::ParseError;
+use std::io::{IsTerminal, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// something worth telling the user about a line of the build script output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // 0-based like Spanned::line_number, both are None if the problem is not tied to a line
+    pub line_number: Option<usize>,
+    pub line: Option<String>,
+    // the lines right before `line`, oldest first
+    pub context: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(error: &ParseError, context: Vec<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: error.to_string(),
+            line_number: error.line_number(),
+            line: error.line().map(str::to_string),
+            context,
+        }
+    }
+}
+
+// receives every diagnostic of a parse, e.g. Vec<Diagnostic> to capture them or
+// TerminalRenderer to print them
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic: Diagnostic);
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.push(diagnostic);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    // colored if stderr is a terminal and NO_COLOR is not set, see https://no-color.org
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stderr().is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// writes diagnostics to stderr like rustc does:
+//
+// warning: falling back to the bundled sqlite
+//   --> build-script-build.out:3
+//    |
+//  2 | cargo:rustc-cfg=foo
+//  3 | cargo:warning=falling back to the bundled sqlite
+//    |
+#[derive(Debug, Clone, Default)]
+pub struct TerminalRenderer {
+    // shown in the location line, e.g. the path of the build script output
+    pub file_name: Option<String>,
+    pub color: ColorChoice,
+}
+
+impl TerminalRenderer {
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let color = self.color.enabled();
+        // SGR codes, written directly so ColorChoice::Always is not overruled by the environment
+        let paint = |text: &str, style: &str| {
+            if color {
+                format!("\x1b[{style}m{text}\x1b[0m")
+            } else {
+                text.to_string()
+            }
+        };
+        let (label, style) = match diagnostic.severity {
+            Severity::Error => ("error", "1;31"),
+            Severity::Warning => ("warning", "1;33"),
+        };
+        let mut out = format!(
+            "{}{} {}\n",
+            paint(label, style),
+            paint(":", "1"),
+            diagnostic.message
+        );
+        let (Some(line_number), Some(line)) = (diagnostic.line_number, &diagnostic.line) else {
+            return out;
+        };
+
+        // 1-based for humans, the gutter is one column wider than the last line number
+        let last = line_number + 1;
+        let width = last.to_string().len() + 1;
+        let blue = |text: &str| paint(text, "1;34");
+        let file_name = self.file_name.as_deref().unwrap_or("<build script output>");
+        out += &format!("{:width$}{} {file_name}:{last}\n", "", blue("-->"));
+        out += &format!("{:width$} {}\n", "", blue("|"));
+        let first = last.saturating_sub(diagnostic.context.len());
+        for (i, line) in diagnostic.context.iter().enumerate() {
+            out += &format!("{} {line}\n", blue(&format!("{:>width$} |", first + i)));
+        }
+        out += &format!(
+            "{} {}\n",
+            blue(&format!("{last:>width$} |")),
+            paint(line, style)
+        );
+        out += &format!("{:width$} {}\n", "", blue("|"));
+        out
+    }
+}
+
+impl DiagnosticSink for TerminalRenderer {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        let rendered = self.render(&diagnostic);
+        let _ = std::io::stderr().write_all(rendered.as_bytes());
+    }
+}
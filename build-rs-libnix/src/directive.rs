@@ -0,0 +1,466 @@
+use crate::cfg::{is_reserved_cfg, parse_cfg, validate_check_cfg};
+use crate::error::ParseError;
+use crate::link::{LinkLib, LinkSearch, parse_link_lib, parse_link_search};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::BufRead;
+
+// cargo:KEY=VALUE or cargo::KEY=VALUE, see
+// https://doc.rust-lang.org/cargo/reference/build-scripts.html#outputs-of-the-build-script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    Old,
+    New,
+}
+
+// a value together with the (0-based) line of the build script output it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub line_number: usize,
+    pub line: String,
+    pub value: T,
+}
+
+// which targets a cargo:rustc-link-arg* directive applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkArgTarget {
+    All,
+    Cdylib,
+    Bin(String),
+    Bins,
+    Tests,
+    Examples,
+    Benches,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    // cargo:rustc-cfg=NAME or cargo:rustc-cfg=NAME="VALUE"
+    RustcCfg {
+        name: String,
+        value: Option<String>,
+    },
+    RustcCheckCfg {
+        value: String,
+    },
+    LinkLib(LinkLib),
+    LinkSearch(LinkSearch),
+    // cargo:rustc-flags=-l foo -L native=/path
+    RustcFlags {
+        link_search: Vec<LinkSearch>,
+        link_lib: Vec<LinkLib>,
+    },
+    LinkArg {
+        target: LinkArgTarget,
+        flag: String,
+    },
+    Env {
+        key: String,
+        value: String,
+    },
+    Metadata {
+        key: String,
+        value: String,
+    },
+    RerunIfChanged {
+        path: OsString,
+    },
+    RerunIfEnvChanged {
+        name: String,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    // directives without a meaning for the nix build, see the README
+    Ignored {
+        key: String,
+        value: String,
+    },
+    // cargo::KEY=VALUE with a KEY cargo does not know, see Policy::unknown
+    Unknown {
+        key: String,
+        value: String,
+    },
+}
+
+pub(crate) fn split_once_byte(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|b| *b == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+// paths do not have to be valid UTF-8 on unix
+#[cfg(unix)]
+pub(crate) fn os_string_from_bytes(bytes: &[u8]) -> Option<OsString> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(std::ffi::OsStr::from_bytes(bytes).to_os_string())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn os_string_from_bytes(bytes: &[u8]) -> Option<OsString> {
+    std::str::from_utf8(bytes).ok().map(OsString::from)
+}
+
+// function adapted from cargo src/cargo/core/compiler/custom_build.rs `fn parse_rustc_flags`
+// only -l and -L are allowed, either attached (-lfoo) or separated (-l foo)
+fn parse_rustc_flags(value: &str) -> Result<(Vec<&str>, Vec<&str>), String> {
+    let mut flags_iter = value.split_whitespace();
+    let (mut library_paths, mut library_links) = (Vec::new(), Vec::new());
+
+    while let Some(flag) = flags_iter.next() {
+        if flag.starts_with("-l") || flag.starts_with("-L") {
+            let (flag, mut value) = flag.split_at(2);
+            if value.is_empty() {
+                value = match flags_iter.next() {
+                    Some(v) => v,
+                    None => return Err(format!("flag '{flag}' in rustc-flags has no value")),
+                }
+            }
+            match flag {
+                "-l" => library_links.push(value),
+                "-L" => library_paths.push(value),
+                _ => unreachable!(),
+            }
+        } else {
+            return Err(format!(
+                "only '-l' and '-L' flags are allowed in rustc-flags, found '{flag}'"
+            ));
+        }
+    }
+    Ok((library_paths, library_links))
+}
+
+// parses a single line of build script output, Ok(None) for lines which are not a directive
+fn parse_line(line_number: usize, bytes: &[u8]) -> Result<Option<Spanned<Directive>>, ParseError> {
+    let bytes = bytes.trim_ascii(); // Remove the trailing \n or \r\n and any whitespace
+
+    // cargo::KEY=VALUE is the syntax since rust 1.77, cargo:KEY=VALUE the older one
+    let (syntax, data) = if let Some(data) = bytes.strip_prefix(b"cargo::") {
+        (Syntax::New, data)
+    } else if let Some(data) = bytes.strip_prefix(b"cargo:") {
+        (Syntax::Old, data)
+    } else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(bytes).into_owned();
+    let spanned = |value: Directive| Spanned {
+        line_number,
+        line: line.clone(),
+        value,
+    };
+
+    let Some((command, arg)) =
+        split_once_byte(data, b'=').filter(|(command, arg)| !command.is_empty() && !arg.is_empty())
+    else {
+        return Err(ParseError::MalformedDirective { line_number, line });
+    };
+    let Ok(command) = std::str::from_utf8(command) else {
+        return Err(ParseError::MalformedDirective { line_number, line });
+    };
+
+    // warnings and errors are only shown, so invalid bytes are replaced there
+    // and paths are kept as they are, every other value has to be valid UTF-8
+    match command {
+        "warning" => {
+            return Ok(Some(spanned(Directive::Warning {
+                message: String::from_utf8_lossy(arg).into_owned(),
+            })));
+        }
+        "error" => {
+            return Ok(Some(spanned(Directive::Error {
+                message: String::from_utf8_lossy(arg).into_owned(),
+            })));
+        }
+        // cargo:rustc-link-search=native=/build/tmp.X3Lovygu3U
+        // libsqlite3-sys> cargo:rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib
+        "rustc-link-search" => {
+            return match parse_link_search(arg) {
+                Ok(link_search) => Ok(Some(spanned(Directive::LinkSearch(link_search)))),
+                Err(reason) => Err(ParseError::MalformedLinkSearch {
+                    line_number,
+                    line,
+                    reason,
+                }),
+            };
+        }
+        "rerun-if-changed" => {
+            return match os_string_from_bytes(arg) {
+                Some(path) => Ok(Some(spanned(Directive::RerunIfChanged { path }))),
+                None => Err(ParseError::UnrepresentablePath { line_number, line }),
+            };
+        }
+        _ => {}
+    }
+    let Ok(arg) = std::str::from_utf8(arg) else {
+        return Err(ParseError::InvalidUtf8 {
+            line_number,
+            line,
+            key: command.to_string(),
+        });
+    };
+
+    let directive = match command {
+        // rustc
+        "rustc-cfg" => match parse_cfg(arg) {
+            Ok((name, value)) if is_reserved_cfg(&name, value.as_deref()) => {
+                return Err(ParseError::ReservedCfg {
+                    line_number,
+                    line,
+                    name,
+                });
+            }
+            Ok((name, value)) => Directive::RustcCfg { name, value },
+            Err(reason) => {
+                return Err(ParseError::MalformedCfg {
+                    line_number,
+                    line,
+                    reason,
+                });
+            }
+        },
+        "rustc-check-cfg" => match validate_check_cfg(arg) {
+            Ok(()) => Directive::RustcCheckCfg {
+                value: arg.to_string(),
+            },
+            Err(reason) => {
+                return Err(ParseError::MalformedCheckCfg {
+                    line_number,
+                    line,
+                    reason,
+                });
+            }
+        },
+
+        // env - cargo:rustc-env=VAR=VALUE
+        "rustc-env" => match arg.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Directive::Env {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+            _ => return Err(ParseError::MalformedEnv { line_number, line }),
+        },
+
+        // cargo:rustc-link-lib=static=sqlite3
+        "rustc-link-lib" => match parse_link_lib(arg) {
+            Ok(link_lib) => Directive::LinkLib(link_lib),
+            Err(reason) => {
+                return Err(ParseError::MalformedLinkLib {
+                    line_number,
+                    line,
+                    reason,
+                });
+            }
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-flags
+        // cargo:rustc-flags=-l dylib=foo -L native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib
+        // cargo:rustc-flags=-lfoo -Lnative=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib
+        "rustc-flags" => {
+            let (library_paths, library_links) = match parse_rustc_flags(arg) {
+                Ok(flags) => flags,
+                Err(reason) => {
+                    return Err(ParseError::MalformedRustcFlags {
+                        line_number,
+                        line,
+                        reason,
+                    });
+                }
+            };
+            let mut link_search = vec![];
+            for path in library_paths {
+                match parse_link_search(path.as_bytes()) {
+                    Ok(search) => link_search.push(search),
+                    Err(reason) => {
+                        return Err(ParseError::MalformedRustcFlags {
+                            line_number,
+                            line,
+                            reason: format!("invalid -L argument '{path}': {reason}"),
+                        });
+                    }
+                }
+            }
+            let mut link_lib = vec![];
+            for link in library_links {
+                match parse_link_lib(link) {
+                    Ok(lib) => link_lib.push(lib),
+                    Err(reason) => {
+                        return Err(ParseError::MalformedRustcFlags {
+                            line_number,
+                            line,
+                            reason: format!("invalid -l argument '{link}': {reason}"),
+                        });
+                    }
+                }
+            }
+            Directive::RustcFlags {
+                link_search,
+                link_lib,
+            }
+        }
+
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg
+        // cargo:rustc-link-arg=-Wl,-soname,libfoo.so.1
+        "rustc-link-arg" => Directive::LinkArg {
+            target: LinkArgTarget::All,
+            flag: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-cdylib-link-arg
+        // cargo::rustc-link-arg-cdylib=FLAG
+        "rustc-cdylib-link-arg" | "rustc-link-arg-cdylib" => Directive::LinkArg {
+            target: LinkArgTarget::Cdylib,
+            flag: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-bin
+        // cargo:rustc-link-arg-bin=BIN=FLAG
+        "rustc-link-arg-bin" => match arg.split_once('=') {
+            Some((bin, flag)) if !bin.is_empty() => {
+                // the name becomes a file name in rustc-link-arg-bin/
+                if bin.contains(['/', '\\']) || bin == "." || bin == ".." {
+                    return Err(ParseError::InvalidBinName {
+                        line_number,
+                        line,
+                        bin: bin.to_string(),
+                    });
+                }
+                Directive::LinkArg {
+                    target: LinkArgTarget::Bin(bin.to_string()),
+                    flag: flag.to_string(),
+                }
+            }
+            _ => return Err(ParseError::MalformedLinkArgBin { line_number, line }),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-bins
+        "rustc-link-arg-bins" => Directive::LinkArg {
+            target: LinkArgTarget::Bins,
+            flag: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-tests
+        "rustc-link-arg-tests" => Directive::LinkArg {
+            target: LinkArgTarget::Tests,
+            flag: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-examples
+        "rustc-link-arg-examples" => Directive::LinkArg {
+            target: LinkArgTarget::Examples,
+            flag: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-benches
+        // cargo::rustc-link-arg-benches=FLAG
+        // cargo:rustc-link-arg-benches=-rdynamic
+        "rustc-link-arg-benches" => Directive::LinkArg {
+            target: LinkArgTarget::Benches,
+            flag: arg.to_string(),
+        },
+
+        "rerun-if-env-changed" => Directive::RerunIfEnvChanged {
+            name: arg.to_string(),
+        },
+        // intentionally ignored
+        "rerun-if-changed-bin"
+        | "rerun-if-changed-glob"
+        | "rerun-if-changed-dir"
+        | "rerun-if-changed-recursive"
+        | "rerun-if-changed-env" => Directive::Ignored {
+            key: command.to_string(),
+            value: arg.to_string(),
+        },
+
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#the-links-manifest-key
+        // cargo::metadata=include=/build/libsqlite3-sys-0.31.0/sqlite3
+        "metadata" if syntax == Syntax::New => match arg.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Directive::Metadata {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+            _ => return Err(ParseError::MalformedMetadata { line_number, line }),
+        },
+
+        // cargo rejects unknown keys of the new syntax, whether they fail the run is up to the Policy
+        _ if syntax == Syntax::New => Directive::Unknown {
+            key: command.to_string(),
+            value: arg.to_string(),
+        },
+        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#the-links-manifest-key
+        // every other key of the old syntax is links metadata, for instance
+        // cargo:include=/build/libsqlite3-sys-0.31.0/sqlite3
+        // cargo:root=/nix/store/jndiwzj2zslh1hm7gadhj1rngv7dpgsp-libz-sys-1_1_21-script_build_run-61b385027f328c5a
+        // cargo:conf=OPENSSL_NO_SSL3_METHOD
+        // cargo:version_number=30400010
+        // cargo:lib_dir=/build/tmp.X3Lovygu3U
+        _ => Directive::Metadata {
+            key: command.to_string(),
+            value: arg.to_string(),
+        },
+    };
+
+    Ok(Some(spanned(directive)))
+}
+
+// how many lines before an offending line are kept for diagnostics
+const CONTEXT_LINES: usize = 2;
+
+// yields the directives of a build script output line by line, so only the current line
+// and the few lines before it are kept in memory
+pub struct Directives<R> {
+    reader: R,
+    line_number: usize,
+    buffer: Vec<u8>,
+    recent: VecDeque<String>,
+}
+
+pub fn parse_reader<R: BufRead>(reader: R) -> Directives<R> {
+    Directives {
+        reader,
+        line_number: 0,
+        buffer: Vec::new(),
+        recent: VecDeque::with_capacity(CONTEXT_LINES + 1),
+    }
+}
+
+impl<R> Directives<R> {
+    // the lines right before the last line read, oldest first
+    pub fn context(&self) -> Vec<String> {
+        let before = self.recent.len().saturating_sub(1);
+        self.recent.iter().take(before).cloned().collect()
+    }
+}
+
+impl<R: BufRead> Iterator for Directives<R> {
+    type Item = Result<Spanned<Directive>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buffer.clear();
+            let line_number = self.line_number;
+            match self.reader.read_until(b'\n', &mut self.buffer) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    self.line_number += 1;
+                    if self.recent.len() > CONTEXT_LINES {
+                        self.recent.pop_front();
+                    }
+                    self.recent.push_back(
+                        String::from_utf8_lossy(self.buffer.trim_ascii_end()).into_owned(),
+                    );
+                }
+                Err(e) => {
+                    self.line_number += 1;
+                    return Some(Err(ParseError::Io {
+                        line_number,
+                        source: e,
+                    }));
+                }
+            }
+            match parse_line(line_number, &self.buffer) {
+                Ok(Some(directive)) => return Some(Ok(directive)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Vec<Spanned<Directive>>, ParseError> {
+    parse_reader(input.as_bytes()).collect()
+}
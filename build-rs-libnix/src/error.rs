@@ -0,0 +1,355 @@
+use std::fmt;
+use std::io;
+
+// everything that can go wrong with a build script output, line_number (0-based, the messages
+// are 1-based) and line point to the offending line so callers can react to each class of failure
+#[derive(Debug)]
+pub enum ParseError {
+    // cargo:KEY=VALUE without a KEY, = or VALUE
+    MalformedDirective {
+        line_number: usize,
+        line: String,
+    },
+    // a cargo::KEY=VALUE key which cargo does not know either
+    UnknownDirective {
+        line_number: usize,
+        line: String,
+        key: String,
+    },
+    // a directive whose Lint is denied by the Policy
+    DeniedDirective {
+        line_number: usize,
+        line: String,
+        key: String,
+    },
+    // only warnings, errors and paths may contain invalid UTF-8
+    InvalidUtf8 {
+        line_number: usize,
+        line: String,
+        key: String,
+    },
+    // cargo:rustc-cfg=NAME or NAME="VALUE"
+    MalformedCfg {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    // a cfg name rustc or cargo set themselves, like feature or target_os
+    ReservedCfg {
+        line_number: usize,
+        line: String,
+        name: String,
+    },
+    // cargo:rustc-check-cfg=cfg(NAME, values("VALUE"))
+    MalformedCheckCfg {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    MalformedEnv {
+        line_number: usize,
+        line: String,
+    },
+    MalformedLinkLib {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    MalformedLinkSearch {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    MalformedRustcFlags {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    MalformedLinkArgBin {
+        line_number: usize,
+        line: String,
+    },
+    InvalidBinName {
+        line_number: usize,
+        line: String,
+        bin: String,
+    },
+    // ParseOptions::bin_names does not contain the binary
+    UnknownBinName {
+        line_number: usize,
+        line: String,
+        bin: String,
+    },
+    MalformedMetadata {
+        line_number: usize,
+        line: String,
+    },
+    // links metadata without ParseOptions::links
+    MissingLinksName {
+        line_number: usize,
+        line: String,
+        key: String,
+    },
+    // a path which is not valid UTF-8 can not be written to the text files
+    UnrepresentablePath {
+        line_number: usize,
+        line: String,
+    },
+    // a rustc-link-search path whose PathRewrite rule is PathAction::Error
+    DeniedPath {
+        line_number: usize,
+        line: String,
+        path: String,
+    },
+    // cargo:error=MESSAGE
+    ScriptError {
+        line_number: usize,
+        line: String,
+        message: String,
+    },
+    // several problems at once, e.g. every cargo:error of a build script
+    Multiple(Vec<ParseError>),
+    Io {
+        line_number: usize,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MalformedDirective { line_number, line } => {
+                write!(
+                    f,
+                    "Unknown command to parse on line {}: '{line}'",
+                    line_number + 1
+                )
+            }
+            ParseError::UnknownDirective {
+                line_number,
+                line,
+                key,
+            } => write!(
+                f,
+                "Unknown key: '{key}' on line {}: '{line}'",
+                line_number + 1
+            ),
+            ParseError::DeniedDirective {
+                line_number,
+                line,
+                key,
+            } => write!(
+                f,
+                "'{key}' on line {} is denied by the policy: '{line}'",
+                line_number + 1
+            ),
+            ParseError::InvalidUtf8 {
+                line_number,
+                line,
+                key,
+            } => write!(
+                f,
+                "The value of '{key}' on line {} is not valid UTF-8: '{line}'",
+                line_number + 1
+            ),
+            ParseError::MalformedCfg {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Unable to parse rustc-cfg argument on line {}: '{line}': {reason}",
+                line_number + 1
+            ),
+            ParseError::ReservedCfg {
+                line_number,
+                line,
+                name,
+            } => write!(
+                f,
+                "The cfg '{name}' on line {} is reserved and can not be set by a build script: '{line}'",
+                line_number + 1
+            ),
+            ParseError::MalformedCheckCfg {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Unable to parse rustc-check-cfg argument on line {}: '{line}': {reason}",
+                line_number + 1
+            ),
+            ParseError::MalformedEnv { line_number, line } => {
+                write!(
+                    f,
+                    "Unable to parse rustc-env argument on line {}: '{line}'",
+                    line_number + 1
+                )
+            }
+            ParseError::MalformedLinkLib {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Unable to parse rustc-link-lib argument on line {}: '{line}': {reason}",
+                line_number + 1
+            ),
+            ParseError::MalformedLinkSearch {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Unable to parse rustc-link-search argument on line {}: '{line}': {reason}",
+                line_number + 1
+            ),
+            ParseError::MalformedRustcFlags {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Unable to parse rustc-flags argument on line {}: '{line}': {reason}",
+                line_number + 1
+            ),
+            ParseError::MalformedLinkArgBin { line_number, line } => write!(
+                f,
+                "Unable to parse rustc-link-arg-bin argument on line {}: '{line}', expected 'BIN=FLAG'",
+                line_number + 1
+            ),
+            ParseError::InvalidBinName {
+                line_number,
+                line,
+                bin,
+            } => write!(
+                f,
+                "Invalid binary name '{bin}' in rustc-link-arg-bin on line {}: '{line}'",
+                line_number + 1
+            ),
+            ParseError::UnknownBinName {
+                line_number,
+                line,
+                bin,
+            } => write!(
+                f,
+                "The package does not contain a binary target named '{bin}', rustc-link-arg-bin on line {}: '{line}'",
+                line_number + 1
+            ),
+            ParseError::MalformedMetadata { line_number, line } => write!(
+                f,
+                "Unable to parse metadata argument on line {}: '{line}', expected 'cargo::metadata=KEY=VALUE'",
+                line_number + 1
+            ),
+            ParseError::MissingLinksName {
+                line_number,
+                line,
+                key,
+            } => write!(
+                f,
+                "Links metadata '{key}' on line {} needs the links name of the package, which is not set: '{line}'",
+                line_number + 1
+            ),
+            ParseError::UnrepresentablePath { line_number, line } => write!(
+                f,
+                "The path on line {} is not valid UTF-8 and can not be written: '{line}'",
+                line_number + 1
+            ),
+            ParseError::DeniedPath {
+                line_number,
+                line,
+                path,
+            } => write!(
+                f,
+                "The rustc-link-search path '{path}' on line {} is not allowed by the rewrite rules: '{line}'",
+                line_number + 1
+            ),
+            ParseError::ScriptError {
+                line_number,
+                message,
+                ..
+            } => write!(
+                f,
+                "The build script reported an error on line {}: {message}",
+                line_number + 1
+            ),
+            ParseError::Multiple(errors) => {
+                write!(f, "{} error(s):", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {error}")?;
+                }
+                Ok(())
+            }
+            ParseError::Io {
+                line_number,
+                source,
+            } => {
+                write!(f, "Unable to read line {}: {source}", line_number + 1)
+            }
+        }
+    }
+}
+
+impl ParseError {
+    // the (0-based) line the error points to, None for Multiple
+    pub fn line_number(&self) -> Option<usize> {
+        match self {
+            ParseError::MalformedDirective { line_number, .. }
+            | ParseError::UnknownDirective { line_number, .. }
+            | ParseError::DeniedDirective { line_number, .. }
+            | ParseError::InvalidUtf8 { line_number, .. }
+            | ParseError::MalformedCfg { line_number, .. }
+            | ParseError::ReservedCfg { line_number, .. }
+            | ParseError::MalformedCheckCfg { line_number, .. }
+            | ParseError::MalformedEnv { line_number, .. }
+            | ParseError::MalformedLinkLib { line_number, .. }
+            | ParseError::MalformedLinkSearch { line_number, .. }
+            | ParseError::MalformedRustcFlags { line_number, .. }
+            | ParseError::MalformedLinkArgBin { line_number, .. }
+            | ParseError::InvalidBinName { line_number, .. }
+            | ParseError::UnknownBinName { line_number, .. }
+            | ParseError::MalformedMetadata { line_number, .. }
+            | ParseError::MissingLinksName { line_number, .. }
+            | ParseError::UnrepresentablePath { line_number, .. }
+            | ParseError::DeniedPath { line_number, .. }
+            | ParseError::ScriptError { line_number, .. }
+            | ParseError::Io { line_number, .. } => Some(*line_number),
+            ParseError::Multiple(_) => None,
+        }
+    }
+
+    // the offending line of the build script output, None for Multiple and Io
+    pub fn line(&self) -> Option<&str> {
+        match self {
+            ParseError::MalformedDirective { line, .. }
+            | ParseError::UnknownDirective { line, .. }
+            | ParseError::DeniedDirective { line, .. }
+            | ParseError::InvalidUtf8 { line, .. }
+            | ParseError::MalformedCfg { line, .. }
+            | ParseError::ReservedCfg { line, .. }
+            | ParseError::MalformedCheckCfg { line, .. }
+            | ParseError::MalformedEnv { line, .. }
+            | ParseError::MalformedLinkLib { line, .. }
+            | ParseError::MalformedLinkSearch { line, .. }
+            | ParseError::MalformedRustcFlags { line, .. }
+            | ParseError::MalformedLinkArgBin { line, .. }
+            | ParseError::InvalidBinName { line, .. }
+            | ParseError::UnknownBinName { line, .. }
+            | ParseError::MalformedMetadata { line, .. }
+            | ParseError::MissingLinksName { line, .. }
+            | ParseError::UnrepresentablePath { line, .. }
+            | ParseError::DeniedPath { line, .. }
+            | ParseError::ScriptError { line, .. } => Some(line),
+            ParseError::Multiple(_) | ParseError::Io { .. } => None,
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,24 +1,76 @@
 use anyhow::{Context, Result};
-use colored::*;
-use regex::Regex;
-use std::fs;
+use quote::{double_quote_escape, double_quote_template};
+use rewrite::relocate;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+mod cfg;
+mod diagnostic;
+mod directive;
+mod error;
+mod link;
+mod nix;
+mod output;
+mod policy;
+mod quote;
+mod rewrite;
+#[allow(clippy::module_inception)]
 mod tests;
+mod writer;
 
-#[derive(Debug)]
+pub use diagnostic::{ColorChoice, Diagnostic, DiagnosticSink, Severity, TerminalRenderer};
+pub use directive::{Directive, Directives, LinkArgTarget, Spanned, parse, parse_reader};
+pub use error::ParseError;
+pub use link::{LinkKind, LinkLib, LinkModifier, LinkSearch, LinkSearchKind};
+pub use nix::nix_string;
+pub use output::{
+    BuildScriptOutput, Cfg, EnvVar, IgnoredDirective, LinkArgs, Message, Metadata, SCHEMA_VERSION,
+};
+pub use policy::{Level, Lint, Policy};
+pub use quote::shell_quote;
+pub use rewrite::{PathAction, PathRewrite, PathRule, Relocation, RewrittenPath};
+pub use writer::{
+    ArgfileWriter, EnvNulWriter, EnvScriptWriter, JsonWriter, NixWriter, OutputWriter, TextWriter,
+    output_writer, output_writers,
+};
+
+#[derive(Debug, Default)]
 pub struct TheResult {
     pub rustc_arguments: Vec<String>,
     pub rustc_propagated_arguments: Vec<String>,
     pub environment_variables: Vec<String>,
-    pub metadata: Vec<String>,
+    pub metadata: Vec<(String, String)>,
     pub rustc_flags: Vec<String>,
+    pub rustc_link_arg: Vec<String>,
     pub rustc_link_arg_cdylib: Vec<String>,
-    pub rustc_link_arg_bin: Vec<String>,
+    pub rustc_link_arg_bin: BTreeMap<String, Vec<String>>,
     pub rustc_link_arg_bins: Vec<String>,
     pub rustc_link_arg_tests: Vec<String>,
     pub rustc_link_arg_examples: Vec<String>,
     pub rustc_link_arg_benches: Vec<String>,
+    // the same values without shell quoting, for build-script.json
+    pub output: BuildScriptOutput,
+}
+
+// the context of the package whose build script output is parsed
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    // the `links` key of the package, needed for links metadata (DEP_<LINKS>_<KEY>)
+    pub links: Option<String>,
+    // the binary targets of the package, with them an unknown BIN in rustc-link-arg-bin=BIN=FLAG is an error
+    pub bin_names: Option<Vec<String>>,
+    // cargo:error only prints the message instead of failing, which is meant for debugging
+    pub allow_script_errors: bool,
+    // go on after a malformed directive and fail with every error of the output at the end
+    pub collect_errors: bool,
+    // which of the directives that are not written to the files are fine
+    pub policy: Policy,
+    // how rustc-link-search paths are passed to rustc for this crate
+    pub link_search_paths: PathRewrite,
+    // path prefixes replaced in the values of every other directive, e.g. the OUT_DIR of the build script
+    pub relocations: Vec<Relocation>,
 }
 
 pub trait EnvifyExt: ToString {
@@ -35,249 +87,441 @@ impl EnvifyExt for String {
     }
 }
 
-pub fn process_buildrs_output(in_path: &Path, out_dir: &Path) -> Result<()> {
-    let input = fs::read_to_string(in_path).expect("Could not read file");
-    match handle_content(input) {
-        Ok(out) => {
-            let rustc_arguments_path = Path::new(&out_dir).join("rustc-arguments");
-            std::fs::write(rustc_arguments_path, out.rustc_arguments.join(" "))
-                .expect("Unable to write data to file");
-
-            let rustc_propagated_arguments_path =
-                Path::new(&out_dir).join("rustc-propagated-arguments");
-            std::fs::write(
-                rustc_propagated_arguments_path,
-                out.rustc_propagated_arguments.join(" "),
-            )
-            .expect("Unable to write data to file");
-
-            let environment_variables_path = Path::new(&out_dir).join("environment-variables");
-            std::fs::write(
-                environment_variables_path,
-                out.environment_variables.join("\n"),
-            )
-            .expect("Unable to write data to file");
-
-            let rustc_link_arg_benches_path = Path::new(&out_dir).join("rustc-link-arg-benches");
-            std::fs::write(
-                rustc_link_arg_benches_path,
-                out.rustc_link_arg_benches.join(" "),
-            )
-            .expect("Unable to write data to file");
+// KEY='VALUE' for the environment-variables file, the shell writers check that KEY is usable there
+fn environment_variable(key: &str, value: &str) -> String {
+    format!("{}={}", key, shell_quote(value))
+}
 
-            println!(
-                "build.rs related nix files written to '{}'",
-                out_dir.display()
-            );
-            Ok(())
-        }
-        Err(e) => anyhow::bail!(e),
-    }
+// links metadata is passed to the dependents as DEP_<LINKS>_<KEY>
+fn dep_environment_variable_name(links: &str, key: &str) -> String {
+    format!(
+        "DEP_{}_{}",
+        links.to_string().envify(),
+        key.to_string().envify()
+    )
 }
 
-fn eprintln_document_with_error(input: String, error_line: usize) {
-    for (line_number, line) in input.lines().enumerate() {
-        let formatted_line_number = format!("{:3}   ", line_number);
-        if line_number == error_line {
-            eprintln!("> {} {}", formatted_line_number, line.red());
-        } else {
-            eprintln!("  {} {}", formatted_line_number, line);
-        }
+// cargo:rustc-cfg=fast_arithmetic="64"
+fn rustc_cfg_argument(name: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("--cfg {}", shell_quote(&format!("{}=\"{}\"", name, value))),
+        None => format!("--cfg {}", shell_quote(name)),
     }
 }
 
-fn eprintln_document_with_warning(input: String, error_line: usize) {
-    eprintln!(
-        "{}",
-        "The following build.rs 'cargo:' directive will be ignored:".yellow()
-    );
-    for (line_number, line) in input.lines().enumerate() {
-        let formatted_line_number = format!("{:3}   ", line_number);
-        if line_number == error_line {
-            eprintln!("> {} {}", formatted_line_number, line.yellow());
-        } else {
-            eprintln!("  {} {}", formatted_line_number, line);
-        }
-    }
+// cargo:rustc-link-lib=static=sqlite3
+fn rustc_link_lib_argument(link_lib: &LinkLib) -> String {
+    format!("-l {}", shell_quote(&link_lib.to_string()))
 }
 
-pub fn handle_content(input: String) -> Result<TheResult> {
-    let mut rustc_arguments: Vec<String> = vec![];
-    let mut rustc_propagated_arguments: Vec<String> = vec![];
-    let mut environment_variables: Vec<String> = vec![];
-    let metadata: Vec<String> = vec![];
-    let rustc_flags: Vec<String> = vec![];
-    let rustc_link_arg_cdylib: Vec<String> = vec![];
-    let rustc_link_arg_bin: Vec<String> = vec![];
-    let rustc_link_arg_bins: Vec<String> = vec![];
-    let rustc_link_arg_tests: Vec<String> = vec![];
-    let rustc_link_arg_examples: Vec<String> = vec![];
-    let mut rustc_link_arg_benches: Vec<String> = vec![];
+// cargo:rustc-link-arg=-Wl,-soname,libfoo.so.1
+fn rustc_link_arg_argument(flag: &str) -> String {
+    format!("-C link-arg={}", shell_quote(flag))
+}
 
-    for (line_number, line) in input.lines().enumerate() {
-        let trimmed_line = line.trim();
-        if !trimmed_line.starts_with("cargo:") {
-            continue;
+// cargo:rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib
+// returns the argument for this crate, with the path rewritten by `rewrite`, the unchanged one
+// propagated to its dependents and the rewritten path itself
+fn rustc_link_search_arguments(
+    link_search: &LinkSearch,
+    rewrite: &PathRewrite,
+    line_number: usize,
+    line: &str,
+) -> Result<(String, String, LinkSearch), ParseError> {
+    let Some(path) = link_search.path.to_str() else {
+        return Err(ParseError::UnrepresentablePath {
+            line_number,
+            line: line.to_string(),
+        });
+    };
+    let (argument, rewritten_path) = match rewrite.rewrite(&link_search.path) {
+        RewrittenPath::Kept(_) => (
+            format!("-L {}", shell_quote(&link_search.to_string())),
+            link_search.path.clone(),
+        ),
+        RewrittenPath::Replaced(replacement, rest) => {
+            let mut rewritten = double_quote_template(replacement);
+            let mut rewritten_path = replacement.to_string();
+            if !rest.as_os_str().is_empty() {
+                rewritten.truncate(rewritten.trim_end_matches('/').len());
+                rewritten.push('/');
+                rewritten.push_str(&double_quote_escape(&rest.to_string_lossy()));
+                rewritten_path.truncate(rewritten_path.trim_end_matches('/').len());
+                rewritten_path.push('/');
+                rewritten_path.push_str(&rest.to_string_lossy());
+            }
+            (
+                format!(
+                    "-L \"{}={}\"",
+                    double_quote_escape(link_search.kind.as_str()),
+                    rewritten
+                ),
+                rewritten_path.into(),
+            )
+        }
+        RewrittenPath::Denied => {
+            return Err(ParseError::DeniedPath {
+                line_number,
+                line: line.to_string(),
+                path: path.to_string(),
+            });
         }
+    };
+    Ok((
+        argument,
+        format!("-L {}", shell_quote(&link_search.to_string())),
+        LinkSearch {
+            kind: link_search.kind,
+            path: rewritten_path,
+        },
+    ))
+}
 
-        let line = line.trim(); // Remove any trailing newline or whitespace
-        let re = Regex::new(r"^cargo:([^=]+)\s*=\s*(.+)$")
-            .context("Regex error constructing cargo metadata regex")?;
+// parses the build script output at `in_path` and writes the text files to `out_dir`
+pub fn process_buildrs_output(
+    in_path: &Path,
+    out_dir: &Path,
+    options: &ParseOptions,
+) -> Result<()> {
+    let mut renderer = TerminalRenderer {
+        file_name: Some(in_path.display().to_string()),
+        ..TerminalRenderer::default()
+    };
+    process_buildrs_output_with(
+        in_path,
+        out_dir,
+        options,
+        &[Box::new(TextWriter)],
+        &mut renderer,
+    )
+}
 
-        if let Some(caps) = re.captures(line) {
-            let command = &caps[1];
-            let arg = &caps[2];
+// like process_buildrs_output, but with the given writers and warnings and errors go to `sink`,
+// a ParseError has already been reported to `sink` when it is returned
+pub fn process_buildrs_output_with(
+    in_path: &Path,
+    out_dir: &Path,
+    options: &ParseOptions,
+    writers: &[Box<dyn OutputWriter>],
+    sink: &mut dyn DiagnosticSink,
+) -> Result<()> {
+    let file = File::open(in_path)
+        .with_context(|| format!("Could not read file '{}'", in_path.display()))?;
+    let out = handle_reader_with(BufReader::new(file), options, sink)?;
+    for writer in writers {
+        writer.check(&out)?;
+    }
+    for writer in writers {
+        writer.write(&out, out_dir)?;
+    }
+    println!(
+        "build.rs related nix files written to '{}'",
+        out_dir.display()
+    );
+    Ok(())
+}
 
-            match command {
-                // rustc
-                "rustc-cfg" => rustc_arguments.push(format!("--cfg '{}'", arg)),
-                "rustc-check-cfg" => rustc_arguments.push(format!("--check-cfg '{}'", arg)),
+// the cargo key of a directive with a Lint
+fn lint_key(directive: &Directive) -> &str {
+    match directive {
+        Directive::RerunIfChanged { .. } => "rerun-if-changed",
+        Directive::RerunIfEnvChanged { .. } => "rerun-if-env-changed",
+        Directive::Ignored { key, .. } | Directive::Unknown { key, .. } => key,
+        _ => "",
+    }
+}
 
-                // env - cargo:rustc-env=VAR=VALUE 
-                "rustc-env" => {
-                    let re = Regex::new(r"^(.+)\s*=\s*(.*)$")
-                        .context("Regex error constructing cargo metadata regex")?;
-                    if let Some(caps) = re.captures(&arg) {
-                        let key = &caps[1];
-                        let val = &caps[2];
-                        environment_variables.push(format!("{}='{}'", key, val))
-                    } else {
-                        eprintln_document_with_error(input.clone(), line_number);
-                        anyhow::bail!("Unable to parse rustc-env argument at {line_number}: '{line}'")
-                    }
-                },
+fn lint_message(lint: Lint, directive: &Directive) -> String {
+    let key = lint_key(directive);
+    match lint {
+        Lint::Unknown => format!("unknown key '{key}' is ignored"),
+        Lint::Rerun => format!("'{key}' has no effect on the nix build"),
+    }
+}
 
-                "warning" => {
-                    eprintln!("\x1b[1;33mwarning\x1b[0m: {arg}");
-                },
-                "error" => {
-                    eprintln!("\x1b[1;31error\x1b[0m: {arg}");
-                },
+fn denied_error(lint: Lint, directive: Spanned<Directive>) -> ParseError {
+    let key = lint_key(&directive.value).to_string();
+    let Spanned {
+        line_number, line, ..
+    } = directive;
+    match lint {
+        Lint::Unknown => ParseError::UnknownDirective {
+            line_number,
+            line,
+            key,
+        },
+        Lint::Rerun => ParseError::DeniedDirective {
+            line_number,
+            line,
+            key,
+        },
+    }
+}
 
-                // cargo:rustc-link-lib=static=sqlite3
-                "rustc-link-lib" => rustc_arguments.push(format!("-l '{}'", arg)),
-                // cargo:rustc-link-search=native=/build/tmp.X3Lovygu3U
-                // libsqlite3-sys> cargo:rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib
-                "rustc-link-search" => {
-                    rustc_propagated_arguments.push(format!("-L '{}'", arg));
-                    let re = Regex::new(r"^(.+)\s*=\s*(.+)$")
-                        .context("Regex error constructing cargo metadata regex")?;
-                    if let Some(caps) = re.captures(&arg) {
-                        let mode = &caps[1];
-                        let _directory = &caps[2];
-                        // if directory.starts_with("/nix/store") {
-                            // rustc_arguments.push(format!("-L \"{}={}\"", mode, directory));
-                        // } else {
-                            rustc_arguments.push(format!("-L \"{}=$out\"", mode));
-                        // }
-                    } else {
-                        eprintln_document_with_error(input.clone(), line_number);
-                        anyhow::bail!("Unable to parse rustc-link-search argument at {line_number}: '{line}'")
-                    }
-                },
-                // https://rurust.github.io/cargo-docs-ru/build-script.html#the-links-manifest-key
-                // cargo:include=/build/libsqlite3-sys-0.31.0/sqlite3
-                // DEP_{}_INCLUDE='value'
-                "include" => {
-                    let links = std::env::var("CARGO_MANIFEST_LINKS").unwrap().envify();
-                    let key = format!("DEP_{}_INCLUDE", links);
-                    environment_variables.push(format!("{}='{}'", key, arg))
-                },
-                // https://rurust.github.io/cargo-docs-ru/build-script.html#the-links-manifest-key
-                // cargo:root=/nix/store/jndiwzj2zslh1hm7gadhj1rngv7dpgsp-libz-sys-1_1_21-script_build_run-61b385027f328c5a
-                // DEP_{}_ROOT='value'
-                "root" => {
-                    let links = std::env::var("CARGO_MANIFEST_LINKS").unwrap().envify();
-                    let key = format!("DEP_{}_ROOT", links);
-                    environment_variables.push(format!("{}='{}'", key, arg))
-                },
-                // https://rurust.github.io/cargo-docs-ru/build-script.html#the-links-manifest-key
-                // cargo:conf=OPENSSL_NO_SSL3_METHOD
-                // DEP_{}_CONF='value'
-                "conf" => {
-                    let links = std::env::var("CARGO_MANIFEST_LINKS").unwrap().envify();
-                    let key = format!("DEP_{}_CONF", links);
-                    environment_variables.push(format!("{}='{}'", key, arg))
-                },
+pub fn handle_content(input: String) -> Result<TheResult, ParseError> {
+    handle_content_with(input, &ParseOptions::default())
+}
 
-                // https://rurust.github.io/cargo-docs-ru/build-script.html#the-links-manifest-key
-                // cargo:version_number=30400010
-                // DEP_{}_VERSION_NUMBER='value'
-                "version_number" => {
-                    let links = std::env::var("CARGO_MANIFEST_LINKS").unwrap().envify();
-                    let key = format!("DEP_{}_VERSION_NUMBER", links);
-                    environment_variables.push(format!("{}='{}'", key, arg))   
-                },
-                // https://rurust.github.io/cargo-docs-ru/build-script.html#the-links-manifest-key
-                // cargo:static=1
-                // DEP_{}_STATIC='1'
-                "static" => {
-                    let links = std::env::var("CARGO_MANIFEST_LINKS").unwrap().envify();
-                    let key = format!("DEP_{}_STATIC", links);
-                    environment_variables.push(format!("{}='{}'", key, arg))
-                },
-                // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-benches
-                // cargo::rustc-link-arg-benches=FLAG
-                // cargo:rustc-link-arg-benches=-rdynamic 
-                "rustc-link-arg-benches" => {
-                    rustc_link_arg_benches.push(format!("-C link-arg='{}'", arg));
-                },
+pub fn handle_content_with(input: String, options: &ParseOptions) -> Result<TheResult, ParseError> {
+    handle_reader(input.as_bytes(), options)
+}
 
-                // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-cdylib-link-arg
-                // cargo::rustc-link-arg-cdylib=FLAG
-                "rustc-cdylib-link-arg" |
-                "rustc-link-arg-cdylib" => {
-                    eprintln_document_with_error(input.clone(), line_number);
-                    anyhow::bail!("Command: '{command}' on line: '{line_number}' not implemented yet!")
-                },
+// like handle_content_with, but reads the build script output line by line
+pub fn handle_reader<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<TheResult, ParseError> {
+    handle_reader_with(reader, options, &mut TerminalRenderer::default())
+}
 
-                // intentionally ignored 
-                "lib_dir" => {}, // cargo:lib_dir=/build/tmp.X3Lovygu3U
-                "rerun-if-changed" => {},
-                "rerun-if-env-changed" => {}, 
-                "rerun-if-changed-bin" => {},
-                "rerun-if-changed-glob" => {},
-                "rerun-if-changed-dir" => {},
-                "rerun-if-changed-recursive" => {},
-                "rerun-if-changed-env" => {},
+// like handle_reader, but warnings and errors go to `sink` instead of stderr
+pub fn handle_reader_with<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<TheResult, ParseError> {
+    let mut the_result = TheResult::default();
+    let mut errors: Vec<ParseError> = vec![];
+    let mut script_errors: Vec<ParseError> = vec![];
 
-                // failing, to be implemented (without usecase/example yet)
-                "metadata" |                   // https://doc.rust-lang.org/cargo/reference/build-scripts.html#the-links-manifest-key
-                "rustc-flags" |                // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-flags
-                "rustc-link-arg" |             // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg
-                "rustc-link-arg-bin" |         // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-bin
-                "rustc-link-arg-bins" |        // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-bins
-                "rustc-link-arg-tests" |       // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-tests
-                "rustc-link-arg-examples"      // https://doc.rust-lang.org/cargo/reference/build-scripts.html#rustc-link-arg-examples
-                => {
-                    eprintln_document_with_error(input.clone(), line_number);
-                    let version = format!("{} version {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-                    anyhow::bail!("Command: '{command}' on line: '{line_number}' not implemented yet! {version}")
+    let mut directives = parse_reader(reader);
+    while let Some(directive) = directives.next() {
+        let result = directive.and_then(|directive| {
+            if let Some(lint) = Lint::of(&directive.value) {
+                match options.policy.level(lint) {
+                    Level::Allow => {}
+                    Level::Warn => sink.report(Diagnostic {
+                        severity: Severity::Warning,
+                        message: lint_message(lint, &directive.value),
+                        line_number: Some(directive.line_number),
+                        line: Some(directive.line.clone()),
+                        context: directives.context(),
+                    }),
+                    Level::Deny => return Err(denied_error(lint, directive)),
                 }
-                _ => {
-                    eprintln_document_with_warning(input.clone(), line_number);
-                    continue
-                },
             }
-        } else {
-            eprintln_document_with_error(input.clone(), line_number);
-            anyhow::bail!("Unknown command to parse on line {line_number}: '{line}'")
-        };
+            match directive.value {
+                Directive::Warning { message } => {
+                    the_result.output.warnings.push(Message {
+                        line: directive.line_number + 1,
+                        message: message.clone(),
+                    });
+                    sink.report(Diagnostic {
+                        severity: Severity::Warning,
+                        message,
+                        line_number: Some(directive.line_number),
+                        line: Some(directive.line),
+                        context: directives.context(),
+                    });
+                    Ok(())
+                }
+                // like cargo, the build fails after all directives were processed
+                Directive::Error { message } => {
+                    the_result.output.errors.push(Message {
+                        line: directive.line_number + 1,
+                        message: message.clone(),
+                    });
+                    sink.report(Diagnostic {
+                        severity: Severity::Error,
+                        message: message.clone(),
+                        line_number: Some(directive.line_number),
+                        line: Some(directive.line.clone()),
+                        context: directives.context(),
+                    });
+                    script_errors.push(ParseError::ScriptError {
+                        line_number: directive.line_number,
+                        line: directive.line,
+                        message,
+                    });
+                    Ok(())
+                }
+                value => the_result.push_directive(
+                    Spanned {
+                        line_number: directive.line_number,
+                        line: directive.line,
+                        value,
+                    },
+                    options,
+                ),
+            }
+        });
+        if let Err(e) = result {
+            sink.report(Diagnostic::error(&e, directives.context()));
+            // a failing reader would fail again on the next line
+            if !options.collect_errors || matches!(e, ParseError::Io { .. }) {
+                return Err(e);
+            }
+            errors.push(e);
+        }
     }
 
-    let the_result = TheResult {
-        rustc_arguments,
-        rustc_propagated_arguments,
-        environment_variables,
-        metadata,
-        rustc_flags,
-        rustc_link_arg_cdylib,
-        rustc_link_arg_bin,
-        rustc_link_arg_bins,
-        rustc_link_arg_tests,
-        rustc_link_arg_examples,
-        rustc_link_arg_benches,
-    };
+    if !options.allow_script_errors {
+        errors.append(&mut script_errors);
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(ParseError::line_number);
+        return Err(ParseError::Multiple(errors));
+    }
 
     Ok(the_result)
 }
+
+impl TheResult {
+    // renders a single directive, cargo:warning and cargo:error are reported by the caller
+    fn push_directive(
+        &mut self,
+        directive: Spanned<Directive>,
+        options: &ParseOptions,
+    ) -> Result<(), ParseError> {
+        let Spanned {
+            line_number,
+            line,
+            mut value,
+        } = directive;
+        relocate(&mut value, &options.relocations);
+        match value {
+            Directive::RustcCfg { name, value } => {
+                self.rustc_arguments
+                    .push(rustc_cfg_argument(&name, value.as_deref()));
+                self.output.cfgs.push(Cfg { name, value });
+            }
+            Directive::RustcCheckCfg { value } => {
+                self.rustc_arguments
+                    .push(format!("--check-cfg {}", shell_quote(&value)));
+                self.output.check_cfgs.push(value);
+            }
+            Directive::Env { key, value } => {
+                self.environment_variables
+                    .push(environment_variable(&key, &value));
+                self.output.env.push(EnvVar { name: key, value });
+            }
+            Directive::LinkLib(link_lib) => {
+                self.rustc_arguments
+                    .push(rustc_link_lib_argument(&link_lib));
+                self.output.link_libs.push(link_lib);
+            }
+            Directive::LinkSearch(link_search) => {
+                let (argument, propagated_argument, rewritten) = rustc_link_search_arguments(
+                    &link_search,
+                    &options.link_search_paths,
+                    line_number,
+                    &line,
+                )?;
+                self.rustc_propagated_arguments.push(propagated_argument);
+                self.rustc_arguments.push(argument);
+                self.output.link_search.push(rewritten);
+                self.output.propagated_link_search.push(link_search);
+            }
+            // routed like rustc-link-search and rustc-link-lib, but also kept in rustc_flags
+            Directive::RustcFlags {
+                link_search,
+                link_lib,
+            } => {
+                let mut flags = vec![];
+                for link_search in link_search {
+                    let (argument, propagated_argument, rewritten) = rustc_link_search_arguments(
+                        &link_search,
+                        &options.link_search_paths,
+                        line_number,
+                        &line,
+                    )?;
+                    self.rustc_flags.push(propagated_argument.clone());
+                    self.rustc_propagated_arguments.push(propagated_argument);
+                    self.rustc_arguments.push(argument);
+                    flags.push(format!("-L {link_search}"));
+                    self.output.link_search.push(rewritten);
+                    self.output.propagated_link_search.push(link_search);
+                }
+                for link_lib in link_lib {
+                    self.rustc_flags.push(rustc_link_lib_argument(&link_lib));
+                    self.rustc_arguments
+                        .push(rustc_link_lib_argument(&link_lib));
+                    flags.push(format!("-l {link_lib}"));
+                    self.output.link_libs.push(link_lib);
+                }
+                self.output.rustc_flags.push(flags.join(" "));
+            }
+            Directive::LinkArg { target, flag } => {
+                let argument = rustc_link_arg_argument(&flag);
+                let link_args = &mut self.output.link_args;
+                match target {
+                    LinkArgTarget::All => {
+                        self.rustc_link_arg.push(argument);
+                        link_args.all.push(flag);
+                    }
+                    LinkArgTarget::Cdylib => {
+                        self.rustc_link_arg_cdylib.push(argument);
+                        link_args.cdylib.push(flag);
+                    }
+                    LinkArgTarget::Bin(bin) => {
+                        if let Some(bin_names) = &options.bin_names
+                            && !bin_names.contains(&bin)
+                        {
+                            return Err(ParseError::UnknownBinName {
+                                line_number,
+                                line,
+                                bin,
+                            });
+                        }
+                        self.rustc_link_arg_bin
+                            .entry(bin.clone())
+                            .or_default()
+                            .push(argument);
+                        link_args.bin.entry(bin).or_default().push(flag);
+                    }
+                    LinkArgTarget::Bins => {
+                        self.rustc_link_arg_bins.push(argument);
+                        link_args.bins.push(flag);
+                    }
+                    LinkArgTarget::Tests => {
+                        self.rustc_link_arg_tests.push(argument);
+                        link_args.tests.push(flag);
+                    }
+                    LinkArgTarget::Examples => {
+                        self.rustc_link_arg_examples.push(argument);
+                        link_args.examples.push(flag);
+                    }
+                    LinkArgTarget::Benches => {
+                        self.rustc_link_arg_benches.push(argument);
+                        link_args.benches.push(flag);
+                    }
+                }
+            }
+            Directive::Metadata { key, value } => {
+                let Some(links) = &options.links else {
+                    return Err(ParseError::MissingLinksName {
+                        line_number,
+                        line,
+                        key,
+                    });
+                };
+                let name = dep_environment_variable_name(links, &key);
+                self.environment_variables
+                    .push(environment_variable(&name, &value));
+                self.metadata.push((key.clone(), value.clone()));
+                self.output.metadata.push(Metadata { key, value, name });
+            }
+            // only listed in build-script.json
+            Directive::RerunIfChanged { path } => self.output.ignored.push(IgnoredDirective {
+                line: line_number + 1,
+                key: "rerun-if-changed".to_string(),
+                value: path.to_string_lossy().into_owned(),
+            }),
+            Directive::RerunIfEnvChanged { name } => self.output.ignored.push(IgnoredDirective {
+                line: line_number + 1,
+                key: "rerun-if-env-changed".to_string(),
+                value: name,
+            }),
+            Directive::Ignored { key, value } | Directive::Unknown { key, value } => {
+                self.output.ignored.push(IgnoredDirective {
+                    line: line_number + 1,
+                    key,
+                    value,
+                })
+            }
+            // intentionally ignored
+            Directive::Warning { .. } | Directive::Error { .. } => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::directive::{os_string_from_bytes, split_once_byte};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+// https://doc.rust-lang.org/rustc/command-line-arguments.html#-l-link-the-generated-crate-to-a-native-library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkKind {
+    Static,
+    Dylib,
+    Framework,
+}
+
+impl LinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkKind::Static => "static",
+            LinkKind::Dylib => "dylib",
+            LinkKind::Framework => "framework",
+        }
+    }
+}
+
+// ordered like they are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkModifier {
+    Bundle,
+    WholeArchive,
+    Verbatim,
+    AsNeeded,
+}
+
+impl LinkModifier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkModifier::Bundle => "bundle",
+            LinkModifier::WholeArchive => "whole-archive",
+            LinkModifier::Verbatim => "verbatim",
+            LinkModifier::AsNeeded => "as-needed",
+        }
+    }
+}
+
+// cargo:rustc-link-lib=[KIND[:MODIFIERS]=]NAME[:RENAME]
+// cargo:rustc-link-lib=static:+whole-archive,-bundle=foo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LinkLib {
+    // None lets rustc pick, which is dylib unless a static library is found
+    pub kind: Option<LinkKind>,
+    // true for +MODIFIER, false for -MODIFIER
+    pub modifiers: BTreeMap<LinkModifier, bool>,
+    pub name: String,
+    pub rename: Option<String>,
+}
+
+impl LinkLib {
+    pub fn is_static(&self) -> bool {
+        self.kind == Some(LinkKind::Static)
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.kind, Some(LinkKind::Dylib | LinkKind::Framework))
+    }
+}
+
+// canonical: the modifiers are sorted, e.g. static:-bundle,+whole-archive=foo:bar
+impl fmt::Display for LinkLib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(kind) = &self.kind {
+            write!(f, "{}", kind.as_str())?;
+            let modifiers: Vec<String> = self
+                .modifiers
+                .iter()
+                .map(|(modifier, enabled)| {
+                    format!("{}{}", if *enabled { '+' } else { '-' }, modifier.as_str())
+                })
+                .collect();
+            if !modifiers.is_empty() {
+                write!(f, ":{}", modifiers.join(","))?;
+            }
+            write!(f, "=")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(rename) = &self.rename {
+            write!(f, ":{rename}")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_link_kind(kind: &str) -> Result<LinkKind, String> {
+    match kind {
+        "static" => Ok(LinkKind::Static),
+        "dylib" => Ok(LinkKind::Dylib),
+        "framework" => Ok(LinkKind::Framework),
+        _ => Err(format!(
+            "unknown library kind '{kind}', expected 'static', 'dylib' or 'framework'"
+        )),
+    }
+}
+
+// the rules of rustc_session::config::parse_native_lib_modifiers
+fn parse_link_modifiers(
+    kind: LinkKind,
+    modifiers: &str,
+) -> Result<BTreeMap<LinkModifier, bool>, String> {
+    let mut parsed = BTreeMap::new();
+    for modifier in modifiers.split(',') {
+        let (enabled, name) = match modifier.split_at_checked(1) {
+            Some(("+", name)) => (true, name),
+            Some(("-", name)) => (false, name),
+            _ => {
+                return Err(format!(
+                    "linking modifier '{modifier}' has to start with '+' or '-'"
+                ));
+            }
+        };
+        let link_modifier = match name {
+            "bundle" => LinkModifier::Bundle,
+            "whole-archive" => LinkModifier::WholeArchive,
+            "verbatim" => LinkModifier::Verbatim,
+            "as-needed" => LinkModifier::AsNeeded,
+            _ => {
+                return Err(format!(
+                    "unknown linking modifier '{name}', expected 'bundle', 'whole-archive', 'verbatim' or 'as-needed'"
+                ));
+            }
+        };
+        match (link_modifier, kind) {
+            (LinkModifier::Bundle | LinkModifier::WholeArchive, LinkKind::Static)
+            | (LinkModifier::AsNeeded, LinkKind::Dylib | LinkKind::Framework)
+            | (LinkModifier::Verbatim, _) => {}
+            (LinkModifier::Bundle | LinkModifier::WholeArchive, _) => {
+                return Err(format!(
+                    "linking modifier '{name}' is only compatible with the 'static' kind"
+                ));
+            }
+            (LinkModifier::AsNeeded, _) => {
+                return Err(format!(
+                    "linking modifier '{name}' is only compatible with the 'dylib' and 'framework' kinds"
+                ));
+            }
+        }
+        if parsed.insert(link_modifier, enabled).is_some() {
+            return Err(format!(
+                "linking modifier '{name}' is specified more than once"
+            ));
+        }
+    }
+    Ok(parsed)
+}
+
+// cargo:rustc-link-lib=[KIND[:MODIFIERS]=]NAME[:RENAME]
+pub(crate) fn parse_link_lib(arg: &str) -> Result<LinkLib, String> {
+    let (kind, modifiers, rest) = match arg.split_once('=') {
+        Some((kind_and_modifiers, rest)) => match kind_and_modifiers.split_once(':') {
+            Some((kind, modifiers)) => {
+                let kind = parse_link_kind(kind)?;
+                (Some(kind), parse_link_modifiers(kind, modifiers)?, rest)
+            }
+            None => (
+                Some(parse_link_kind(kind_and_modifiers)?),
+                BTreeMap::new(),
+                rest,
+            ),
+        },
+        None => (None, BTreeMap::new(), arg),
+    };
+    let (name, rename) = match rest.split_once(':') {
+        Some((_, "")) => return Err("the new name after ':' is empty".to_string()),
+        Some((name, rename)) => (name, Some(rename.to_string())),
+        None => (rest, None),
+    };
+    if name.is_empty() {
+        return Err("the library name is empty".to_string());
+    }
+    Ok(LinkLib {
+        kind,
+        modifiers,
+        name: name.to_string(),
+        rename,
+    })
+}
+
+// https://doc.rust-lang.org/rustc/command-line-arguments.html#-l-add-a-directory-to-the-library-search-path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkSearchKind {
+    Native,
+    Dependency,
+    Crate,
+    Framework,
+    All,
+}
+
+impl LinkSearchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkSearchKind::Native => "native",
+            LinkSearchKind::Dependency => "dependency",
+            LinkSearchKind::Crate => "crate",
+            LinkSearchKind::Framework => "framework",
+            LinkSearchKind::All => "all",
+        }
+    }
+}
+
+// cargo:rustc-link-search=[KIND=]PATH
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LinkSearch {
+    pub kind: LinkSearchKind,
+    pub path: PathBuf,
+}
+
+// the kind is always written, a bare path becomes all=PATH
+impl fmt::Display for LinkSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.kind.as_str(), self.path.to_string_lossy())
+    }
+}
+
+fn parse_link_search_kind(kind: &[u8]) -> Option<LinkSearchKind> {
+    match kind {
+        b"native" => Some(LinkSearchKind::Native),
+        b"dependency" => Some(LinkSearchKind::Dependency),
+        b"crate" => Some(LinkSearchKind::Crate),
+        b"framework" => Some(LinkSearchKind::Framework),
+        b"all" => Some(LinkSearchKind::All),
+        _ => None,
+    }
+}
+
+// cargo:rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib
+// cargo:rustc-link-search=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib
+pub(crate) fn parse_link_search(arg: &[u8]) -> Result<LinkSearch, String> {
+    let (kind, path) = match split_once_byte(arg, b'=') {
+        Some((kind, path)) => match parse_link_search_kind(kind) {
+            Some(kind) => (kind, path),
+            // like rustc, anything else is part of the path, but a word like 'nativ' is a typo
+            None if !kind.is_empty()
+                && kind
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'-') =>
+            {
+                return Err(format!(
+                    "unknown search kind '{}', expected 'native', 'dependency', 'crate', 'framework' or 'all'",
+                    String::from_utf8_lossy(kind)
+                ));
+            }
+            None => (LinkSearchKind::All, arg),
+        },
+        None => (LinkSearchKind::All, arg),
+    };
+    if path.is_empty() {
+        return Err("the path is empty".to_string());
+    }
+    match os_string_from_bytes(path) {
+        Some(path) => Ok(LinkSearch {
+            kind,
+            path: path.into(),
+        }),
+        None => Err("the path can not be represented on this platform".to_string()),
+    }
+}
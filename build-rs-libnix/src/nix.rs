@@ -0,0 +1,135 @@
+use crate::output::BuildScriptOutput;
+use std::collections::BTreeMap;
+
+// a nix string literal, ${ would start an interpolation
+// it's "${quoted}" -> "it's \"\${quoted}\""
+pub fn nix_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// a nix identifier can be used as attribute name as it is, everything else is quoted
+fn nix_attribute_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !matches!(
+            name,
+            "assert" | "else" | "if" | "in" | "inherit" | "let" | "or" | "rec" | "then" | "with"
+        );
+    if is_identifier {
+        name.to_string()
+    } else {
+        nix_string(name)
+    }
+}
+
+fn nix_list<'a>(values: impl IntoIterator<Item = &'a String>) -> String {
+    let values: Vec<String> = values.into_iter().map(|value| nix_string(value)).collect();
+    if values.is_empty() {
+        "[ ]".to_string()
+    } else {
+        format!("[ {} ]", values.join(" "))
+    }
+}
+
+// the attribute set of build-script.nix, indented by `indent` spaces
+fn nix_attrs(attrs: &[(String, String)], indent: usize) -> String {
+    if attrs.is_empty() {
+        return "{ }".to_string();
+    }
+    let mut nix = "{\n".to_string();
+    for (name, value) in attrs {
+        nix.push_str(&format!(
+            "{:indent$}{} = {};\n",
+            "",
+            nix_attribute_name(name),
+            value,
+            indent = indent + 2
+        ));
+    }
+    nix.push_str(&format!("{:indent$}}}", ""));
+    nix
+}
+
+// like cargo, a variable set twice gets the last value
+fn nix_variables<'a>(variables: impl IntoIterator<Item = (&'a String, &'a String)>) -> String {
+    let variables: BTreeMap<&String, &String> = variables.into_iter().collect();
+    let attrs: Vec<(String, String)> = variables
+        .into_iter()
+        .map(|(name, value)| (name.clone(), nix_string(value)))
+        .collect();
+    nix_attrs(&attrs, 2)
+}
+
+impl BuildScriptOutput {
+    // the content of build-script.nix, an attribute set which can be imported at evaluation time
+    pub fn to_nix(&self) -> String {
+        let cfgs: Vec<String> = self.cfgs.iter().map(|cfg| cfg.to_string()).collect();
+        let link_libs: Vec<String> = self.link_libs.iter().map(|lib| lib.to_string()).collect();
+        let link_search: Vec<String> = self.link_search.iter().map(|s| s.to_string()).collect();
+        let propagated_link_search: Vec<String> = self
+            .propagated_link_search
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let link_args = &self.link_args;
+        let bin: Vec<(String, String)> = link_args
+            .bin
+            .iter()
+            .map(|(name, flags)| (name.clone(), nix_list(flags)))
+            .collect();
+        let link_args = nix_attrs(
+            &[
+                ("all".to_string(), nix_list(&link_args.all)),
+                ("cdylib".to_string(), nix_list(&link_args.cdylib)),
+                ("bin".to_string(), nix_attrs(&bin, 4)),
+                ("bins".to_string(), nix_list(&link_args.bins)),
+                ("tests".to_string(), nix_list(&link_args.tests)),
+                ("examples".to_string(), nix_list(&link_args.examples)),
+                ("benches".to_string(), nix_list(&link_args.benches)),
+            ],
+            2,
+        );
+        let attrs = [
+            ("cfgs".to_string(), nix_list(&cfgs)),
+            ("checkCfgs".to_string(), nix_list(&self.check_cfgs)),
+            ("linkLibs".to_string(), nix_list(&link_libs)),
+            ("linkSearch".to_string(), nix_list(&link_search)),
+            (
+                "propagatedLinkSearch".to_string(),
+                nix_list(&propagated_link_search),
+            ),
+            (
+                "env".to_string(),
+                nix_variables(self.env.iter().map(|env| (&env.name, &env.value))),
+            ),
+            ("linkArgs".to_string(), link_args),
+            (
+                "depMetadata".to_string(),
+                nix_variables(
+                    self.metadata
+                        .iter()
+                        .map(|metadata| (&metadata.name, &metadata.value)),
+                ),
+            ),
+        ];
+        let mut nix = nix_attrs(&attrs, 0);
+        nix.push('\n');
+        nix
+    }
+}
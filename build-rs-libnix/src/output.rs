@@ -0,0 +1,107 @@
+use crate::link::{LinkLib, LinkSearch};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// the version of build-script.json, bumped when a field changes its meaning or goes away
+pub const SCHEMA_VERSION: u32 = 1;
+
+// cargo:rustc-cfg=NAME or cargo:rustc-cfg=NAME="VALUE", VALUE without the quotes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Cfg {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+// the form rustc takes after --cfg: NAME or NAME="VALUE"
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}=\"{}\"", self.name, value),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// cargo:rustc-env=NAME=VALUE
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+// cargo:metadata=KEY=VALUE, passed to the dependents as the environment variable `name`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Metadata {
+    pub key: String,
+    pub value: String,
+    // DEP_<LINKS>_<KEY>
+    pub name: String,
+}
+
+// the flags of cargo:rustc-link-arg and friends, without the -C link-arg=
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LinkArgs {
+    pub all: Vec<String>,
+    pub cdylib: Vec<String>,
+    pub bin: BTreeMap<String, Vec<String>>,
+    pub bins: Vec<String>,
+    pub tests: Vec<String>,
+    pub examples: Vec<String>,
+    pub benches: Vec<String>,
+}
+
+// cargo:warning=MESSAGE and cargo:error=MESSAGE
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Message {
+    // 1-based, like in the printed diagnostics
+    pub line: usize,
+    pub message: String,
+}
+
+// a directive which is not used for the nix build, e.g. cargo:rerun-if-changed=build.rs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IgnoredDirective {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+// the values of the build script output without any shell quoting, relocated like the text files
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BuildScriptOutput {
+    pub cfgs: Vec<Cfg>,
+    pub check_cfgs: Vec<String>,
+    pub link_libs: Vec<LinkLib>,
+    // the paths for this crate, rewritten by ParseOptions::link_search_paths, a replacement like
+    // $out is kept as it is
+    pub link_search: Vec<LinkSearch>,
+    // the paths for the dependents, as the build script printed them
+    pub propagated_link_search: Vec<LinkSearch>,
+    // one entry per cargo:rustc-flags, its -L and -l are in link_search and link_libs as well
+    pub rustc_flags: Vec<String>,
+    pub env: Vec<EnvVar>,
+    pub metadata: Vec<Metadata>,
+    pub link_args: LinkArgs,
+    pub warnings: Vec<Message>,
+    // only with ParseOptions::allow_script_errors, otherwise the run fails
+    pub errors: Vec<Message>,
+    pub ignored: Vec<IgnoredDirective>,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    output: &'a BuildScriptOutput,
+}
+
+impl BuildScriptOutput {
+    // the content of build-script.json
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&Versioned {
+            schema_version: SCHEMA_VERSION,
+            output: self,
+        })
+    }
+}
@@ -0,0 +1,66 @@
+use crate::directive::Directive;
+
+// what happens to a directive of a Lint category
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    // processed silently
+    Allow,
+    // processed, but reported as a warning
+    Warn,
+    // the run fails
+    Deny,
+}
+
+// directives which do not end up in the written files, see the README
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    // cargo::KEY=VALUE with a KEY cargo does not know
+    Unknown,
+    // cargo:rerun-if-changed, cargo:rerun-if-env-changed and friends, nix rebuilds anyway
+    Rerun,
+}
+
+impl Lint {
+    pub fn of(directive: &Directive) -> Option<Lint> {
+        match directive {
+            Directive::Unknown { .. } => Some(Lint::Unknown),
+            Directive::RerunIfChanged { .. }
+            | Directive::RerunIfEnvChanged { .. }
+            | Directive::Ignored { .. } => Some(Lint::Rerun),
+            _ => None,
+        }
+    }
+}
+
+// how strict the parser is, lenient by default except for unknown keys of the new syntax which
+// cargo rejects as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub unknown: Level,
+    pub rerun: Level,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            unknown: Level::Deny,
+            rerun: Level::Allow,
+        }
+    }
+}
+
+impl Policy {
+    pub fn level(&self, lint: Lint) -> Level {
+        match lint {
+            Lint::Unknown => self.unknown,
+            Lint::Rerun => self.rerun,
+        }
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        match lint {
+            Lint::Unknown => self.unknown = level,
+            Lint::Rerun => self.rerun = level,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// quoting for the shell fragments written to rustc-arguments, environment-variables, ...
+
+// wraps the value in single quotes, a ' inside becomes '\'' so nothing is expanded by the shell
+// /nix/store/...-it's -> '/nix/store/...-it'\''s'
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// escapes the value for use inside double quotes, where the shell still expands a $out placeholder
+pub(crate) fn double_quote_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// a name which can be assigned with NAME=VALUE in a POSIX shell
+pub(crate) fn is_shell_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// like double_quote_escape, but $NAME and ${NAME} references are left for the shell to expand
+// $out/it's "lib" -> $out/it's \"lib\"
+pub(crate) fn double_quote_template(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        if c == '$' {
+            let reference = match rest[1..].strip_prefix('{') {
+                Some(braced) => braced
+                    .find('}')
+                    .filter(|end| is_shell_variable_name(&braced[..*end]))
+                    .map(|end| end + 3),
+                None => Some(
+                    1 + rest[1..]
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len() - 1),
+                )
+                .filter(|end| is_shell_variable_name(&rest[1..*end])),
+            };
+            if let Some(end) = reference {
+                escaped.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+        }
+        escaped.push_str(&double_quote_escape(&rest[..c.len_utf8()]));
+        rest = &rest[c.len_utf8()..];
+    }
+    escaped
+}
@@ -0,0 +1,160 @@
+use crate::directive::Directive;
+use std::path::{Path, PathBuf};
+
+// what happens to a rustc-link-search path before it is passed to rustc for this crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathAction {
+    // the path is used as it is, e.g. for /nix/store paths which outlive the build
+    Keep,
+    // the matched part of the path is replaced, $NAME references are expanded by the shell:
+    // /build/foo-1.0/target/out/lib with prefix /build/foo-1.0/target/out and '$out/out' -> $out/out/lib
+    Replace(String),
+    // the run fails
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRule {
+    pub prefix: PathBuf,
+    pub action: PathAction,
+}
+
+// the rules for rustc-link-search paths, the rule with the longest matching prefix wins and
+// paths without a matching rule get the fallback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRewrite {
+    pub rules: Vec<PathRule>,
+    // Replace replaces the whole path
+    pub fallback: PathAction,
+}
+
+// every path becomes $out, which is what the nix builder copies the libraries to
+impl Default for PathRewrite {
+    fn default() -> Self {
+        PathRewrite {
+            rules: vec![],
+            fallback: PathAction::Replace("$out".to_string()),
+        }
+    }
+}
+
+// the rewritten path of a rustc-link-search directive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewrittenPath<'a> {
+    Kept(&'a Path),
+    // the replacement, a shell fragment, and the rest of the original path
+    Replaced(&'a str, &'a Path),
+    Denied,
+}
+
+impl PathRewrite {
+    pub fn rewrite<'a>(&'a self, path: &'a Path) -> RewrittenPath<'a> {
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| path.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.components().count());
+        match rule {
+            Some(PathRule {
+                prefix,
+                action: PathAction::Replace(replacement),
+            }) => RewrittenPath::Replaced(replacement, path.strip_prefix(prefix).unwrap_or(path)),
+            Some(PathRule { action, .. }) => Self::act(action, path),
+            None => Self::act(&self.fallback, path),
+        }
+    }
+
+    fn act<'a>(action: &'a PathAction, path: &'a Path) -> RewrittenPath<'a> {
+        match action {
+            PathAction::Keep => RewrittenPath::Kept(path),
+            PathAction::Replace(replacement) => RewrittenPath::Replaced(replacement, Path::new("")),
+            PathAction::Error => RewrittenPath::Denied,
+        }
+    }
+}
+
+// replaces an absolute path prefix, e.g. the OUT_DIR of the build script, inside directive values
+// so the written files do not point into a sandbox which is gone after the build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub prefix: String,
+    pub placeholder: String,
+}
+
+impl Relocation {
+    // every occurrence of the prefix which is a whole path, or followed by more path components:
+    // -Wl,-rpath,/build/out/lib -> -Wl,-rpath,@BUILD_OUT_DIR@/lib and -L/build/out/lib ->
+    // -L@BUILD_OUT_DIR@/lib, but /build/outside and /nix/store/abc/build/out stay
+    pub fn apply(&self, value: &str) -> String {
+        let prefix = self.prefix.trim_end_matches('/');
+        // / or an empty prefix would match everywhere
+        if prefix.is_empty() {
+            return value.to_string();
+        }
+        let is_path_char = |c: char| c.is_alphanumeric() || matches!(c, '-' | '_' | '.');
+        let mut relocated = String::with_capacity(value.len());
+        let mut copied = 0;
+        let mut from = 0;
+        while let Some(found) = value[from..].find(prefix) {
+            let start = from + found;
+            let end = start + prefix.len();
+            let at_boundary = (starts_path(&value[..start]) || after_short_option(&value[..start]))
+                && value[end..].chars().next().is_none_or(|c| !is_path_char(c));
+            if at_boundary {
+                relocated.push_str(&value[copied..start]);
+                relocated.push_str(&self.placeholder);
+                copied = end;
+                from = end;
+            } else {
+                // the next match may start inside this one
+                from = start + prefix.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        relocated.push_str(&value[copied..]);
+        relocated
+    }
+}
+
+// a path starts the value or follows a separator like :, =, , or a space, but not more path
+// components or other path name text
+fn starts_path(before: &str) -> bool {
+    before
+        .chars()
+        .next_back()
+        .is_none_or(|c| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')))
+}
+
+// a path right after a short option like -L or -I, which starts the value or follows a separator:
+// -L/build/out/lib or -Wl,-L/build/out/lib
+fn after_short_option(before: &str) -> bool {
+    let option = before.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    option.len() < before.len()
+        && option.strip_suffix('-').is_some_and(|rest| {
+            rest.chars()
+                .next_back()
+                .is_none_or(|c| c.is_whitespace() || matches!(c, ',' | '=' | ':'))
+        })
+}
+
+// applies the relocations, longest prefix first, to every string value of the directive which
+// ends up in the written files, rustc-link-search is left to PathRewrite
+pub(crate) fn relocate(directive: &mut Directive, relocations: &[Relocation]) {
+    if relocations.is_empty() {
+        return;
+    }
+    let mut relocations: Vec<&Relocation> = relocations.iter().collect();
+    relocations.sort_by_key(|relocation| std::cmp::Reverse(relocation.prefix.len()));
+    let value = match directive {
+        Directive::RustcCfg {
+            value: Some(value), ..
+        }
+        | Directive::RustcCheckCfg { value }
+        | Directive::Env { value, .. }
+        | Directive::Metadata { value, .. }
+        | Directive::LinkArg { flag: value, .. } => value,
+        _ => return,
+    };
+    for relocation in relocations {
+        *value = relocation.apply(value);
+    }
+}
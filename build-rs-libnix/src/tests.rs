@@ -1,11 +1,26 @@
 #[cfg(test)]
 mod tests {
-    use crate::handle_content;
-    use std::env;
+    use crate::{
+        ArgfileWriter, ColorChoice, Diagnostic, Directive, EnvNulWriter, EnvScriptWriter,
+        JsonWriter, Level, LinkArgTarget, LinkKind, LinkLib, LinkModifier, LinkSearch,
+        LinkSearchKind, NixWriter, OutputWriter, ParseError, ParseOptions, PathAction, PathRewrite,
+        PathRule, Policy, Relocation, SCHEMA_VERSION, Severity, Spanned, TerminalRenderer,
+        TextWriter, TheResult, handle_content, handle_content_with, handle_reader,
+        handle_reader_with, nix_string, output_writer, output_writers, parse, parse_reader,
+        process_buildrs_output, process_buildrs_output_with, shell_quote,
+    };
     use std::fs;
-    use std::io::Write;
+    use std::io::{BufReader, Write};
+    use std::path::Path;
     use tempfile::NamedTempFile;
 
+    fn mycrate() -> ParseOptions {
+        ParseOptions {
+            links: Some("MYCRATE".to_string()),
+            ..ParseOptions::default()
+        }
+    }
+
     #[test]
     fn test_rustc_cfg_output() {
         let mut temp = NamedTempFile::new().unwrap();
@@ -46,7 +61,7 @@ mod tests {
     #[test]
     fn test_output3() {
         let content = fs::read_to_string("test/output3").unwrap();
-        let output = handle_content(content).unwrap();
+        let output = handle_content_with(content, &mycrate()).unwrap();
         assert_eq!(
             output.rustc_arguments.join(" ").trim(),
             "-l 'static=sqlite3' -L \"native=$out\""
@@ -61,16 +76,15 @@ mod tests {
     fn test_output4() {
         let content = fs::read_to_string("test/output4").unwrap();
         let err = handle_content(content).unwrap_err();
-        assert!(err.to_string().contains("cargo:fail here"));
+        assert!(
+            matches!(err, ParseError::MalformedDirective { line_number: 21, ref line } if line == "cargo:fail here")
+        );
     }
 
     #[test]
     fn test_output5() {
-        unsafe {
-            env::set_var("CARGO_MANIFEST_LINKS", "MYCRATE");
-        }
         let content = fs::read_to_string("test/output5").unwrap();
-        let output = handle_content(content).unwrap();
+        let output = handle_content_with(content, &mycrate()).unwrap();
         assert_eq!(
             output.rustc_arguments.join(" ").trim(),
             "--check-cfg 'cfg(openssl)' --check-cfg 'cfg(libressl)' --check-cfg 'cfg(boringssl)' --check-cfg 'cfg(libressl250)' --check-cfg 'cfg(libressl251)' --check-cfg 'cfg(libressl252)' --check-cfg 'cfg(libressl261)' --check-cfg 'cfg(libressl270)' --check-cfg 'cfg(libressl271)' --check-cfg 'cfg(libressl273)' --check-cfg 'cfg(libressl280)' --check-cfg 'cfg(libressl281)' --check-cfg 'cfg(libressl291)' --check-cfg 'cfg(libressl310)' --check-cfg 'cfg(libressl321)' --check-cfg 'cfg(libressl332)' --check-cfg 'cfg(libressl340)' --check-cfg 'cfg(libressl350)' --check-cfg 'cfg(libressl360)' --check-cfg 'cfg(libressl361)' --check-cfg 'cfg(libressl370)' --check-cfg 'cfg(libressl380)' --check-cfg 'cfg(libressl381)' --check-cfg 'cfg(libressl382)' --check-cfg 'cfg(libressl390)' --check-cfg 'cfg(libressl400)' --check-cfg 'cfg(libressl410)' --check-cfg 'cfg(ossl101)' --check-cfg 'cfg(ossl102)' --check-cfg 'cfg(ossl102f)' --check-cfg 'cfg(ossl102h)' --check-cfg 'cfg(ossl110)' --check-cfg 'cfg(ossl110f)' --check-cfg 'cfg(ossl110g)' --check-cfg 'cfg(ossl110h)' --check-cfg 'cfg(ossl111)' --check-cfg 'cfg(ossl111b)' --check-cfg 'cfg(ossl111c)' --check-cfg 'cfg(ossl111d)' --check-cfg 'cfg(ossl300)' --check-cfg 'cfg(ossl310)' --check-cfg 'cfg(ossl320)' --check-cfg 'cfg(ossl330)' --check-cfg 'cfg(ossl340)' -L \"native=$out\" -l 'ssl' -l 'crypto' --cfg 'osslconf=\"OPENSSL_NO_SSL3_METHOD\"' --cfg 'openssl' --cfg 'ossl340' --cfg 'ossl330' --cfg 'ossl320' --cfg 'ossl300' --cfg 'ossl101' --cfg 'ossl102' --cfg 'ossl102f' --cfg 'ossl102h' --cfg 'ossl110' --cfg 'ossl110f' --cfg 'ossl110g' --cfg 'ossl110h' --cfg 'ossl111' --cfg 'ossl111b' --cfg 'ossl111c' --cfg 'ossl111d'"
@@ -88,16 +102,22 @@ mod tests {
     #[test]
     fn test_output6() {
         let content = fs::read_to_string("test/output6").unwrap();
-        let output = handle_content(content).unwrap();
+        let output = handle_content_with(content, &mycrate()).unwrap();
+        assert_eq!(output.rustc_arguments.join(" ").trim(), "");
+        assert_eq!(
+            output.metadata,
+            vec![("VERSION".to_string(), "1.2.3".to_string())]
+        );
+        assert_eq!(
+            output.environment_variables.join("\n").trim(),
+            "DEP_MYCRATE_VERSION='1.2.3'"
+        );
     }
 
     #[test]
     fn test_output7() {
-        unsafe {
-            env::set_var("CARGO_MANIFEST_LINKS", "MYCRATE");
-        }
         let content = fs::read_to_string("test/output7").unwrap();
-        let output = handle_content(content).unwrap();
+        let output = handle_content_with(content, &mycrate()).unwrap();
         assert_eq!(
             output.rustc_arguments.join(" ").trim(),
             "-L \"native=$out\" -l 'sqlite3'"
@@ -106,10 +126,1151 @@ mod tests {
             output.rustc_propagated_arguments.join(" ").trim(),
             "-L 'native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-sqlite-3.48.0/lib'"
         );
-        assert_eq!(output.environment_variables.join("\n").trim(), "");
+        assert_eq!(
+            output.environment_variables.join("\n").trim(),
+            "DEP_MYCRATE_LINK_TARGET='sqlite3'"
+        );
         assert_eq!(
             output.rustc_link_arg_benches.join(" ").trim(),
             "-C link-arg='-rdynamic'"
         );
     }
+
+    #[test]
+    fn test_double_colon_syntax() {
+        let content = fs::read_to_string("test/output8").unwrap();
+        let output = handle_content_with(content, &mycrate()).unwrap();
+        assert_eq!(
+            output.rustc_arguments.join(" ").trim(),
+            "--check-cfg 'cfg(has_foo)' --cfg 'has_foo' --cfg 'freebsd11' -l 'static=z' -L \"native=$out\""
+        );
+        assert_eq!(
+            output.environment_variables.join("\n").trim(),
+            "VAR='VALUE'\nDEP_MYCRATE_INCLUDE='/nix/store/k0699a27nkj4c2xn67bjcpfa08nqn9l4-zlib-1.3.1-dev/include'\nDEP_MYCRATE_VERSION_NUMBER='1031'"
+        );
+    }
+
+    #[test]
+    fn test_double_colon_unknown_key() {
+        let err = handle_content("cargo::include=/usr/include".to_string()).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownDirective { ref key, .. } if key == "include"));
+
+        let err = handle_content("cargo::metadata=novalue".to_string()).unwrap_err();
+        assert!(matches!(err, ParseError::MalformedMetadata { .. }));
+    }
+
+    #[test]
+    fn test_links_metadata() {
+        let content = "cargo:rerun-if-changed=build.rs\ncargo:lib_dir=/build/tmp.X3Lovygu3U\ncargo:ssl-version=3.4.1\ncargo:metadata=plain";
+        let output = handle_content_with(content.to_string(), &mycrate()).unwrap();
+        assert_eq!(
+            output.metadata,
+            vec![
+                ("lib_dir".to_string(), "/build/tmp.X3Lovygu3U".to_string()),
+                ("ssl-version".to_string(), "3.4.1".to_string()),
+                ("metadata".to_string(), "plain".to_string()),
+            ]
+        );
+        assert_eq!(
+            output.environment_variables.join("\n").trim(),
+            "DEP_MYCRATE_LIB_DIR='/build/tmp.X3Lovygu3U'\nDEP_MYCRATE_SSL_VERSION='3.4.1'\nDEP_MYCRATE_METADATA='plain'"
+        );
+
+        let err = handle_content(content.to_string()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MissingLinksName { line_number: 1, ref key, .. } if key == "lib_dir"
+        ));
+    }
+
+    #[test]
+    fn test_rustc_flags() {
+        let content = "cargo:rustc-flags=-l dylib=foo -Lnative=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib -lbar";
+        let output = handle_content(content.to_string()).unwrap();
+        assert_eq!(
+            output.rustc_arguments.join(" ").trim(),
+            "-L \"native=$out\" -l 'dylib=foo' -l 'bar'"
+        );
+        assert_eq!(
+            output.rustc_propagated_arguments.join(" ").trim(),
+            "-L 'native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib'"
+        );
+        assert_eq!(
+            output.rustc_flags.join(" ").trim(),
+            "-L 'native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib' -l 'dylib=foo' -l 'bar'"
+        );
+
+        let err = handle_content("cargo:rustc-flags=-lfoo -C opt-level=3".to_string()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedRustcFlags { ref reason, .. } if reason.contains("only '-l' and '-L' flags are allowed")
+        ));
+
+        let err = handle_content("cargo:rustc-flags=-lfoo -L".to_string()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedRustcFlags { ref reason, .. } if reason.contains("has no value")
+        ));
+    }
+
+    #[test]
+    fn test_rustc_link_arg_family() {
+        let content = fs::read_to_string("test/output9").unwrap();
+        let output = handle_content(content).unwrap();
+        assert_eq!(
+            output.rustc_link_arg.join(" ").trim(),
+            "-C link-arg='-Wl,-z,relro'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_cdylib.join(" ").trim(),
+            "-C link-arg='-Wl,-soname,libfoo.so.1' -C link-arg='-Wl,--version-script=foo.map'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_bins.join(" ").trim(),
+            "-C link-arg='-pie'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_tests.join(" ").trim(),
+            "-C link-arg='-Wl,--no-as-needed'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_examples.join(" ").trim(),
+            "-C link-arg='-lm'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_benches.join(" ").trim(),
+            "-C link-arg='-rdynamic'"
+        );
+
+        let out_dir = tempfile::tempdir().unwrap();
+        process_buildrs_output(
+            Path::new("test/output9"),
+            out_dir.path(),
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("rustc-link-arg-cdylib")).unwrap(),
+            "-C link-arg='-Wl,-soname,libfoo.so.1' -C link-arg='-Wl,--version-script=foo.map'"
+        );
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("rustc-link-arg-tests")).unwrap(),
+            "-C link-arg='-Wl,--no-as-needed'"
+        );
+
+        let err = handle_content("cargo:rustc-link-arg-bin=-pie".to_string()).unwrap_err();
+        assert!(matches!(err, ParseError::MalformedLinkArgBin { .. }));
+    }
+
+    #[test]
+    fn test_rustc_link_arg_bin() {
+        let content = "cargo:rustc-link-arg-bin=foo-cli=-Wl,--stack,8388608\ncargo:rustc-link-arg-bin=foo-daemon=-pie\ncargo::rustc-link-arg-bin=foo-cli=-Wl,-z,now";
+        let output = handle_content(content.to_string()).unwrap();
+        assert_eq!(output.rustc_link_arg_bin.len(), 2);
+        assert_eq!(
+            output.rustc_link_arg_bin["foo-cli"].join(" "),
+            "-C link-arg='-Wl,--stack,8388608' -C link-arg='-Wl,-z,now'"
+        );
+        assert_eq!(
+            output.rustc_link_arg_bin["foo-daemon"].join(" "),
+            "-C link-arg='-pie'"
+        );
+
+        let options = ParseOptions {
+            bin_names: Some(vec!["foo-cli".to_string()]),
+            ..ParseOptions::default()
+        };
+        let err = handle_content_with(content.to_string(), &options).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::UnknownBinName { line_number: 1, ref bin, .. } if bin == "foo-daemon"
+        ));
+
+        let err = handle_content("cargo:rustc-link-arg-bin=../foo=-pie".to_string()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidBinName { ref bin, .. } if bin == "../foo"));
+
+        let out_dir = tempfile::tempdir().unwrap();
+        process_buildrs_output(
+            Path::new("test/output9"),
+            out_dir.path(),
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("rustc-link-arg-bin/foo-cli")).unwrap(),
+            "-C link-arg='-Wl,--stack,8388608'"
+        );
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let content = "cargo:error=libfoo not found\ncargo:rustc-cfg=foo\ncargo:warning=falling back\ncargo::error=pkg-config failed";
+        let err = handle_content(content.to_string()).unwrap_err();
+        let ParseError::Multiple(errors) = &err else {
+            panic!("expected all script errors, got {err:?}");
+        };
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::ScriptError { line_number: 0, message: first, .. },
+                ParseError::ScriptError { line_number: 3, message: second, .. },
+            ] if first == "libfoo not found" && second == "pkg-config failed"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "2 error(s):\n  The build script reported an error on line 1: libfoo not found\n  The build script reported an error on line 4: pkg-config failed"
+        );
+
+        let options = ParseOptions {
+            allow_script_errors: true,
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        assert_eq!(output.rustc_arguments.join(" ").trim(), "--cfg 'foo'");
+    }
+
+    #[test]
+    fn test_parse_directives() {
+        let content = "noise\ncargo:rustc-cfg=fast_arithmetic=\"64\"\ncargo:rustc-link-lib=static:+whole-archive,-bundle=foo:bar\ncargo::rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib\ncargo:rustc-env=URL=https://example.org/?a=b\ncargo:rustc-link-arg-bin=foo-cli=-pie\ncargo:rerun-if-changed=build.rs\ncargo:include=/usr/include";
+        let directives = parse(content).unwrap();
+        assert_eq!(
+            directives,
+            vec![
+                Spanned {
+                    line_number: 1,
+                    line: "cargo:rustc-cfg=fast_arithmetic=\"64\"".to_string(),
+                    value: Directive::RustcCfg {
+                        name: "fast_arithmetic".to_string(),
+                        value: Some("64".to_string()),
+                    },
+                },
+                Spanned {
+                    line_number: 2,
+                    line: "cargo:rustc-link-lib=static:+whole-archive,-bundle=foo:bar".to_string(),
+                    value: Directive::LinkLib(LinkLib {
+                        kind: Some(LinkKind::Static),
+                        modifiers: [
+                            (LinkModifier::WholeArchive, true),
+                            (LinkModifier::Bundle, false),
+                        ]
+                        .into(),
+                        name: "foo".to_string(),
+                        rename: Some("bar".to_string()),
+                    }),
+                },
+                Spanned {
+                    line_number: 3,
+                    line: "cargo::rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib".to_string(),
+                    value: Directive::LinkSearch(LinkSearch {
+                        kind: LinkSearchKind::Native,
+                        path: "/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib".into(),
+                    }),
+                },
+                Spanned {
+                    line_number: 4,
+                    line: "cargo:rustc-env=URL=https://example.org/?a=b".to_string(),
+                    value: Directive::Env {
+                        key: "URL".to_string(),
+                        value: "https://example.org/?a=b".to_string(),
+                    },
+                },
+                Spanned {
+                    line_number: 5,
+                    line: "cargo:rustc-link-arg-bin=foo-cli=-pie".to_string(),
+                    value: Directive::LinkArg {
+                        target: LinkArgTarget::Bin("foo-cli".to_string()),
+                        flag: "-pie".to_string(),
+                    },
+                },
+                Spanned {
+                    line_number: 6,
+                    line: "cargo:rerun-if-changed=build.rs".to_string(),
+                    value: Directive::RerunIfChanged {
+                        path: "build.rs".into(),
+                    },
+                },
+                Spanned {
+                    line_number: 7,
+                    line: "cargo:include=/usr/include".to_string(),
+                    value: Directive::Metadata {
+                        key: "include".to_string(),
+                        value: "/usr/include".to_string(),
+                    },
+                },
+            ]
+        );
+
+        let output =
+            handle_content(content.lines().take(3).collect::<Vec<_>>().join("\n")).unwrap();
+        assert_eq!(
+            output.rustc_arguments.join(" "),
+            "--cfg 'fast_arithmetic=\"64\"' -l 'static:-bundle,+whole-archive=foo:bar'"
+        );
+    }
+
+    #[test]
+    fn test_parse_reader() {
+        let file = fs::File::open("test/output7").unwrap();
+        let mut directives = parse_reader(BufReader::new(file));
+        assert_eq!(
+            directives.next().unwrap().unwrap(),
+            Spanned {
+                line_number: 0,
+                line: "cargo:link-target=sqlite3".to_string(),
+                value: Directive::Metadata {
+                    key: "link-target".to_string(),
+                    value: "sqlite3".to_string(),
+                },
+            }
+        );
+        assert_eq!(directives.count(), 3);
+
+        // an error does not end the iteration, the caller decides whether to go on
+        let content = "cargo:fail here\ncargo:rustc-cfg=foo";
+        let results: Vec<_> = parse_reader(content.as_bytes()).collect();
+        assert!(matches!(
+            results[0],
+            Err(ParseError::MalformedDirective { line_number: 0, .. })
+        ));
+        assert_eq!(results[1].as_ref().unwrap().line_number, 1);
+
+        let file = fs::File::open("test/output5").unwrap();
+        let output = handle_reader(BufReader::new(file), &mycrate()).unwrap();
+        assert_eq!(output.rustc_propagated_arguments.len(), 1);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        let values = [
+            "plain",
+            "it's",
+            "$out and ${HOME}",
+            "back\\slash",
+            "\"double\" quotes",
+            "line\nbreak",
+            "`id`",
+            "",
+        ];
+        for value in values {
+            let quoted = shell_quote(value);
+            let echoed = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {quoted}"))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(echoed.stdout).unwrap(), value);
+        }
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_quoted_output() {
+        let content = "cargo:rustc-env=GREETING=it's $HOME\\n\ncargo:rustc-cfg=foo=\"it's\"\ncargo:rustc-link-search=native=/tmp/$out/\"x\"\ncargo:rustc-link-search=na$ti\"ve=/tmp\ncargo:rustc-link-arg=-Wl,-rpath,$ORIGIN\ncargo:include=/tmp/it's";
+        let output = handle_content_with(content.to_string(), &mycrate()).unwrap();
+        assert_eq!(
+            output.environment_variables.join("\n"),
+            "GREETING='it'\\''s $HOME\\n'\nDEP_MYCRATE_INCLUDE='/tmp/it'\\''s'"
+        );
+        assert_eq!(
+            output.rustc_arguments.join(" "),
+            "--cfg 'foo=\"it'\\''s\"' -L \"native=$out\" -L \"all=$out\""
+        );
+        assert_eq!(
+            output.rustc_propagated_arguments.join(" "),
+            "-L 'native=/tmp/$out/\"x\"' -L 'all=na$ti\"ve=/tmp'"
+        );
+        assert_eq!(
+            output.rustc_link_arg.join(" "),
+            "-C link-arg='-Wl,-rpath,$ORIGIN'"
+        );
+
+        // cargo accepts any name, only the shell formats can not write it
+        let output = handle_content_with(
+            "cargo:rustc-env=my.var=1\ncargo:foo.bar=2".to_string(),
+            &mycrate(),
+        )
+        .unwrap();
+        assert_eq!(output.output.env[0].name, "my.var");
+        assert_eq!(output.output.metadata[0].name, "DEP_MYCRATE_FOO.BAR");
+        let out_dir = tempfile::tempdir().unwrap();
+        assert!(TextWriter.write(&output, out_dir.path()).is_err());
+        assert!(!out_dir.path().join("environment-variables").exists());
+        assert!(EnvScriptWriter.write(&output, out_dir.path()).is_err());
+        assert!(!out_dir.path().join("environment-variables.sh").exists());
+        EnvNulWriter.write(&output, out_dir.path()).unwrap();
+        assert_eq!(
+            fs::read(out_dir.path().join("environment-variables.nul")).unwrap(),
+            b"my.var=1\0DEP_MYCRATE_FOO.BAR=2\0"
+        );
+        JsonWriter.write(&output, out_dir.path()).unwrap();
+        NixWriter.write(&output, out_dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_crlf_and_non_utf8() {
+        let content = b"cargo:rustc-cfg=foo\r\ncargo:rustc-env=VAR=VALUE\r\n\xe4rger from cc\r\ncargo:warning=gr\xfc\xdfe\r\n";
+        let output = handle_reader(&content[..], &ParseOptions::default()).unwrap();
+        assert_eq!(output.rustc_arguments.join(" "), "--cfg 'foo'");
+        assert_eq!(output.environment_variables.join("\n"), "VAR='VALUE'");
+
+        let directives: Vec<_> = parse_reader(&content[..])
+            .map(|d| d.unwrap().value)
+            .collect();
+        assert_eq!(
+            directives[2],
+            Directive::Warning {
+                message: "gr\u{fffd}\u{fffd}e".to_string()
+            }
+        );
+
+        // paths are kept as they are, but can not be written to the shell files
+        let content =
+            b"cargo:rerun-if-changed=/build/\xff\ncargo:rustc-link-search=native=/build/\xff";
+        let directives: Vec<_> = parse_reader(&content[..])
+            .map(|d| d.unwrap().value)
+            .collect();
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            assert_eq!(
+                directives[0],
+                Directive::RerunIfChanged {
+                    path: std::ffi::OsStr::from_bytes(b"/build/\xff").into()
+                }
+            );
+        }
+        let err = handle_reader(&content[..], &ParseOptions::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::UnrepresentablePath { line_number: 1, .. }
+        ));
+
+        let err =
+            handle_reader(&b"cargo:rustc-env=VAR=\xff"[..], &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidUtf8 { ref key, .. } if key == "rustc-env"));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let content = "noise\ncargo:rustc-cfg=foo\ncargo:warning=falling back\ncargo:rustc-cfg=bar\nmore noise\ncargo:rustc-link-lib=";
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let err = handle_reader_with(
+            content.as_bytes(),
+            &ParseOptions::default(),
+            &mut diagnostics,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedDirective { line_number: 5, .. }
+        ));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0],
+            Diagnostic {
+                severity: Severity::Warning,
+                message: "falling back".to_string(),
+                line_number: Some(2),
+                line: Some("cargo:warning=falling back".to_string()),
+                context: vec!["noise".to_string(), "cargo:rustc-cfg=foo".to_string()],
+            }
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[1].context,
+            vec!["cargo:rustc-cfg=bar".to_string(), "more noise".to_string()]
+        );
+
+        let renderer = TerminalRenderer {
+            file_name: Some("output".to_string()),
+            color: ColorChoice::Never,
+        };
+        assert_eq!(
+            renderer.render(&diagnostics[0]),
+            "warning: falling back\n  --> output:3\n   |\n 1 | noise\n 2 | cargo:rustc-cfg=foo\n 3 | cargo:warning=falling back\n   |\n"
+        );
+        let renderer = TerminalRenderer {
+            color: ColorChoice::Always,
+            ..renderer
+        };
+        assert!(
+            renderer
+                .render(&diagnostics[1])
+                .starts_with("\x1b[1;31merror\x1b[0m")
+        );
+    }
+
+    #[test]
+    fn test_collect_errors() {
+        let content = "cargo:rustc-cfg=foo\ncargo:fail here\ncargo:error=pkg-config failed\ncargo:rustc-link-arg-bin=-pie\ncargo:warning=falling back\ncargo:include=/usr/include\ncargo:rustc-cfg=bar";
+
+        // fail-fast stays the default
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let err = handle_reader_with(
+            content.as_bytes(),
+            &ParseOptions::default(),
+            &mut diagnostics,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedDirective { line_number: 1, .. }
+        ));
+        assert_eq!(diagnostics.len(), 1);
+
+        let options = ParseOptions {
+            collect_errors: true,
+            ..ParseOptions::default()
+        };
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let err = handle_reader_with(content.as_bytes(), &options, &mut diagnostics).unwrap_err();
+        let ParseError::Multiple(errors) = &err else {
+            panic!("expected all errors, got {err:?}");
+        };
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::MalformedDirective { line_number: 1, .. },
+                ParseError::ScriptError { line_number: 2, .. },
+                ParseError::MalformedLinkArgBin { line_number: 3, .. },
+                ParseError::MissingLinksName { line_number: 5, .. },
+            ]
+        ));
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| (d.severity, d.line_number))
+                .collect::<Vec<_>>(),
+            vec![
+                (Severity::Error, Some(1)),
+                (Severity::Error, Some(2)),
+                (Severity::Error, Some(3)),
+                (Severity::Warning, Some(4)),
+                (Severity::Error, Some(5)),
+            ]
+        );
+
+        let options = ParseOptions {
+            allow_script_errors: true,
+            ..options
+        };
+        let err = handle_reader_with(
+            "cargo:error=ignored\ncargo:fail".as_bytes(),
+            &options,
+            &mut vec![],
+        )
+        .unwrap_err();
+        assert!(matches!(err, ParseError::Multiple(errors) if errors.len() == 1));
+    }
+
+    #[test]
+    fn test_policy() {
+        let content = "cargo:rerun-if-changed=build.rs\ncargo::rerun-if-env-changed=CC\ncargo::foo=bar\ncargo:rustc-cfg=foo";
+
+        // unknown keys of the new syntax are denied and rerun-if-* is allowed by default
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let err = handle_reader_with(
+            content.as_bytes(),
+            &ParseOptions::default(),
+            &mut diagnostics,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, ParseError::UnknownDirective { line_number: 2, ref key, .. } if key == "foo")
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(
+            &parse(content).unwrap()[2].value,
+            Directive::Unknown { key, value } if key == "foo" && value == "bar"
+        ));
+
+        let options = ParseOptions {
+            policy: Policy {
+                unknown: Level::Warn,
+                rerun: Level::Warn,
+            },
+            ..ParseOptions::default()
+        };
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let output = handle_reader_with(content.as_bytes(), &options, &mut diagnostics).unwrap();
+        assert_eq!(output.rustc_arguments.join(" "), "--cfg 'foo'");
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "'rerun-if-changed' has no effect on the nix build",
+                "'rerun-if-env-changed' has no effect on the nix build",
+                "unknown key 'foo' is ignored",
+            ]
+        );
+
+        let options = ParseOptions {
+            policy: Policy {
+                unknown: Level::Allow,
+                rerun: Level::Deny,
+            },
+            ..ParseOptions::default()
+        };
+        let err = handle_content_with(content.to_string(), &options).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::DeniedDirective { line_number: 0, ref key, .. } if key == "rerun-if-changed"
+        ));
+        let output = handle_content_with(
+            content.lines().skip(2).collect::<Vec<_>>().join("\n"),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output.rustc_arguments.join(" "), "--cfg 'foo'");
+    }
+
+    #[test]
+    fn test_cfg_validation() {
+        let content = "cargo:rustc-cfg=foo\ncargo:rustc-cfg=osslconf = \"OPENSSL_NO_SSL3_METHOD\"\ncargo:rustc-cfg=esc=\"a\\\"b\"\ncargo:rustc-check-cfg=cfg(foo)\ncargo:rustc-check-cfg=cfg(a, b, values(\"1\", none(),))\ncargo:rustc-check-cfg=cfg(any())\ncargo:rustc-check-cfg=cfg(target_arch,values(\"mips64r6\"))\ncargo:rustc-check-cfg=cfg()";
+        let output = handle_content(content.to_string()).unwrap();
+        assert_eq!(
+            output.rustc_arguments[..3].join(" "),
+            "--cfg 'foo' --cfg 'osslconf=\"OPENSSL_NO_SSL3_METHOD\"' --cfg 'esc=\"a\\\"b\"'"
+        );
+        assert_eq!(output.rustc_arguments.len(), 8);
+
+        let malformed_cfgs = [
+            "cargo:rustc-cfg=foo=bar",
+            "cargo:rustc-cfg=foo bar",
+            "cargo:rustc-cfg=foo=\"bar",
+            "cargo:rustc-cfg=1foo",
+            "cargo:rustc-cfg=foo(bar)",
+        ];
+        for content in malformed_cfgs {
+            let err = handle_content(content.to_string()).unwrap_err();
+            assert!(
+                matches!(err, ParseError::MalformedCfg { line_number: 0, .. }),
+                "{content}: {err:?}"
+            );
+        }
+
+        for content in [
+            "cargo:rustc-cfg=feature=\"std\"",
+            "cargo::rustc-cfg=target_os=\"linux\"",
+            "cargo:rustc-cfg=debug_assertions",
+            "cargo:rustc-cfg=panic=\"abort\"",
+            "cargo:rustc-cfg=sanitize=\"address\"",
+        ] {
+            let err = handle_content(content.to_string()).unwrap_err();
+            assert!(
+                matches!(err, ParseError::ReservedCfg { .. }),
+                "{content}: {err:?}"
+            );
+        }
+
+        // rustc only rejects the builtin cfgs it sets itself
+        for content in [
+            "cargo:rustc-cfg=test",
+            "cargo:rustc-cfg=target_foo",
+            "cargo:rustc-cfg=sanitizer_foo",
+            "cargo:rustc-cfg=panic=\"foo\"",
+            "cargo:rustc-cfg=target_os",
+            "cargo:rustc-cfg=unix=\"yes\"",
+        ] {
+            handle_content(content.to_string()).unwrap();
+        }
+
+        let malformed_check_cfgs = [
+            "cargo:rustc-check-cfg=foo",
+            "cargo:rustc-check-cfg=cfg(foo",
+            "cargo:rustc-check-cfg=cfg(values(\"a\"), foo)",
+            "cargo:rustc-check-cfg=cfg(foo, values(bar))",
+            "cargo:rustc-check-cfg=cfg(foo) cfg(bar)",
+            "cargo:rustc-check-cfg=cfg(foo=\"bar\")",
+        ];
+        for content in malformed_check_cfgs {
+            let err = handle_content(content.to_string()).unwrap_err();
+            assert!(
+                matches!(err, ParseError::MalformedCheckCfg { line_number: 0, .. }),
+                "{content}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_link_lib() {
+        let content = "cargo:rustc-link-lib=foo\ncargo:rustc-link-lib=dylib=foo:bar\ncargo:rustc-link-lib=framework:+as-needed,+verbatim=CoreFoundation\ncargo:rustc-link-lib=static:+whole-archive,-bundle=sqlite3";
+        let directives = parse(content).unwrap();
+        let link_libs: Vec<&LinkLib> = directives
+            .iter()
+            .map(|d| match &d.value {
+                Directive::LinkLib(link_lib) => link_lib,
+                other => panic!("expected rustc-link-lib, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(link_libs[0].kind, None);
+        assert_eq!(link_libs[1].rename.as_deref(), Some("bar"));
+        assert!(link_libs[1].is_dynamic());
+        assert_eq!(link_libs[2].kind, Some(LinkKind::Framework));
+        assert!(link_libs[3].is_static());
+        assert_eq!(
+            link_libs[3].modifiers.get(&LinkModifier::Bundle),
+            Some(&false)
+        );
+
+        let output = handle_content(content.to_string()).unwrap();
+        assert_eq!(
+            output.rustc_arguments.join(" "),
+            "-l 'foo' -l 'dylib=foo:bar' -l 'framework:+verbatim,+as-needed=CoreFoundation' -l 'static:-bundle,+whole-archive=sqlite3'"
+        );
+
+        let malformed = [
+            (
+                "cargo:rustc-link-lib=statik=foo",
+                "unknown library kind 'statik'",
+            ),
+            (
+                "cargo:rustc-link-lib=static:whole-archive=foo",
+                "has to start with '+' or '-'",
+            ),
+            (
+                "cargo:rustc-link-lib=static:+lazy=foo",
+                "unknown linking modifier 'lazy'",
+            ),
+            (
+                "cargo:rustc-link-lib=dylib:+bundle=foo",
+                "only compatible with the 'static' kind",
+            ),
+            (
+                "cargo:rustc-link-lib=static:+as-needed=foo",
+                "only compatible with the 'dylib' and 'framework' kinds",
+            ),
+            (
+                "cargo:rustc-link-lib=static:+verbatim,-verbatim=foo",
+                "more than once",
+            ),
+            (
+                "cargo:rustc-link-lib=dylib=foo:",
+                "the new name after ':' is empty",
+            ),
+            ("cargo:rustc-link-lib=static=", "the library name is empty"),
+        ];
+        for (content, expected) in malformed {
+            let err = handle_content(content.to_string()).unwrap_err();
+            assert!(
+                matches!(&err, ParseError::MalformedLinkLib { reason, .. } if reason.contains(expected)),
+                "{content}: {err:?}"
+            );
+        }
+        // rustc links a bundled static library as a whole archive as well
+        handle_content("cargo:rustc-link-lib=static:+bundle,+whole-archive=foo".to_string())
+            .unwrap();
+
+        let err = handle_content("cargo:rustc-flags=-l statik=foo".to_string()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedRustcFlags { ref reason, .. } if reason.contains("unknown library kind")
+        ));
+    }
+
+    #[test]
+    fn test_link_search() {
+        let content = "cargo:rustc-link-search=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib\ncargo:rustc-link-search=dependency=/build/deps\ncargo:rustc-link-search=framework=/Library/Frameworks\ncargo:rustc-link-search=crate=/build/crates\ncargo:rustc-link-search=all=/build/all";
+        let kinds: Vec<LinkSearchKind> = parse(content)
+            .unwrap()
+            .into_iter()
+            .map(|d| match d.value {
+                Directive::LinkSearch(link_search) => link_search.kind,
+                other => panic!("expected rustc-link-search, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LinkSearchKind::All,
+                LinkSearchKind::Dependency,
+                LinkSearchKind::Framework,
+                LinkSearchKind::Crate,
+                LinkSearchKind::All,
+            ]
+        );
+
+        let output = handle_content(content.lines().next().unwrap().to_string()).unwrap();
+        assert_eq!(output.rustc_arguments.join(" "), "-L \"all=$out\"");
+        assert_eq!(
+            output.rustc_propagated_arguments.join(" "),
+            "-L 'all=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib'"
+        );
+
+        let err = handle_content("cargo:rustc-link-search=nativ=/usr/lib".to_string()).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MalformedLinkSearch { ref reason, .. } if reason.contains("unknown search kind 'nativ'")
+        ));
+        let err = handle_content("cargo:rustc-link-search=native=".to_string()).unwrap_err();
+        assert!(matches!(err, ParseError::MalformedLinkSearch { .. }));
+
+        let output =
+            handle_content("cargo:rustc-flags=-L /usr/lib -L native=/opt/lib".to_string()).unwrap();
+        assert_eq!(
+            output.rustc_flags.join(" "),
+            "-L 'all=/usr/lib' -L 'native=/opt/lib'"
+        );
+    }
+
+    #[test]
+    fn test_link_search_rewrite() {
+        let content = "cargo:rustc-link-search=native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib\ncargo:rustc-link-search=native=/build/foo-1.0/target/out/lib\ncargo:rustc-link-search=/build/tmp.X3Lovygu3U/it's \"here\"\ncargo:rustc-flags=-L dependency=/build/foo-1.0/target/out";
+        let options = ParseOptions {
+            link_search_paths: PathRewrite {
+                rules: vec![
+                    PathRule {
+                        prefix: "/nix/store".into(),
+                        action: PathAction::Keep,
+                    },
+                    PathRule {
+                        prefix: "/build".into(),
+                        action: PathAction::Replace("@build@".to_string()),
+                    },
+                    PathRule {
+                        prefix: "/build/foo-1.0/target/out".into(),
+                        action: PathAction::Replace("${out}/out/".to_string()),
+                    },
+                ],
+                fallback: PathAction::Error,
+            },
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        assert_eq!(
+            output.rustc_arguments,
+            vec![
+                "-L 'native=/nix/store/yfjzkkkyxcalyj7l1n4d4y6s81i65hmy-foo-1.0/lib'",
+                "-L \"native=${out}/out/lib\"",
+                "-L \"all=@build@/tmp.X3Lovygu3U/it's \\\"here\\\"\"",
+                "-L \"dependency=${out}/out/\"",
+            ]
+        );
+        // the dependents still get the original paths
+        assert_eq!(
+            output.rustc_propagated_arguments[1],
+            "-L 'native=/build/foo-1.0/target/out/lib'"
+        );
+
+        let err = handle_content_with("cargo:rustc-link-search=/usr/lib".to_string(), &options)
+            .unwrap_err();
+        assert!(matches!(err, ParseError::DeniedPath { ref path, .. } if path == "/usr/lib"));
+
+        // the default collapses every path into $out
+        let output = handle_content(content.lines().nth(1).unwrap().to_string()).unwrap();
+        assert_eq!(output.rustc_arguments.join(" "), "-L \"native=$out\"");
+    }
+
+    #[test]
+    fn test_relocations() {
+        let content = "cargo:rustc-env=BINDINGS=/build/foo-1.0/target/out/bindings.rs\ncargo:include=/build/foo-1.0/target/out/include\ncargo:root=/build/tmp.X3Lovygu3U\ncargo:rustc-link-arg=-Wl,-rpath,/build/foo-1.0/target/out/lib\ncargo:rustc-cfg=generated=\"/build/foo-1.0/target/out\"\ncargo:rustc-env=OTHER=/build/foo-1.0/target/outside\ncargo:rustc-link-search=native=/build/foo-1.0/target/out/lib";
+        let options = ParseOptions {
+            links: Some("foo".to_string()),
+            relocations: vec![
+                Relocation {
+                    prefix: "/build".to_string(),
+                    placeholder: "@BUILD@".to_string(),
+                },
+                Relocation {
+                    prefix: "/build/foo-1.0/target/out/".to_string(),
+                    placeholder: "@BUILD_OUT_DIR@".to_string(),
+                },
+            ],
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        assert_eq!(
+            output.environment_variables,
+            vec![
+                "BINDINGS='@BUILD_OUT_DIR@/bindings.rs'",
+                "DEP_FOO_INCLUDE='@BUILD_OUT_DIR@/include'",
+                "DEP_FOO_ROOT='@BUILD@/tmp.X3Lovygu3U'",
+                "OTHER='@BUILD@/foo-1.0/target/outside'",
+            ]
+        );
+        assert_eq!(
+            output.metadata[0],
+            ("include".to_string(), "@BUILD_OUT_DIR@/include".to_string())
+        );
+        assert_eq!(
+            output.rustc_link_arg.join(" "),
+            "-C link-arg='-Wl,-rpath,@BUILD_OUT_DIR@/lib'"
+        );
+        assert_eq!(
+            output.rustc_arguments,
+            vec![
+                "--cfg 'generated=\"@BUILD_OUT_DIR@\"'",
+                "-L \"native=$out\"",
+            ]
+        );
+        // link-search paths are left to the PathRewrite rules
+        assert_eq!(
+            output.rustc_propagated_arguments.join(" "),
+            "-L 'native=/build/foo-1.0/target/out/lib'"
+        );
+
+        let relocation = Relocation {
+            prefix: "/build/out".to_string(),
+            placeholder: "$OUT".to_string(),
+        };
+        assert_eq!(
+            relocation.apply("/build/out:/build/out/lib:/build/outer"),
+            "$OUT:$OUT/lib:/build/outer"
+        );
+        // only a whole path is replaced, not the same components inside another path
+        assert_eq!(
+            relocation.apply("/nix/store/abc/build/out/x,/build/out=a/build/out"),
+            "/nix/store/abc/build/out/x,$OUT=a/build/out"
+        );
+        // a path attached to a short option is a whole path as well
+        assert_eq!(relocation.apply("-L/build/out/lib"), "-L$OUT/lib");
+        assert_eq!(
+            relocation.apply("-O2 -I/build/out/include -I/usr/include"),
+            "-O2 -I$OUT/include -I/usr/include"
+        );
+        assert_eq!(
+            relocation.apply("-Wl,-L/build/out/lib,-rpath=/build/out"),
+            "-Wl,-L$OUT/lib,-rpath=$OUT"
+        );
+        assert_eq!(
+            relocation.apply("--foo-L/build/out a-I/build/out"),
+            "--foo-L/build/out a-I/build/out"
+        );
+        let root = Relocation {
+            prefix: "//".to_string(),
+            placeholder: "$ROOT".to_string(),
+        };
+        assert_eq!(root.apply("/build/out/lib"), "/build/out/lib");
+    }
+
+    #[test]
+    fn test_json_output() {
+        let content = "cargo:rustc-cfg=fast_arithmetic=\"64\"\ncargo:rustc-check-cfg=cfg(has_foo)\ncargo:rustc-link-lib=static:-bundle=foo:bar\ncargo:rustc-link-search=native=/build/foo/lib\ncargo:rustc-flags=-l dylib=z -L /nix/store/abc-zlib/lib\ncargo:rustc-env=GREETING=it's \"quoted\"\ncargo:root=/nix/store/abc-foo\ncargo:rustc-link-arg-bin=server=-Wl,-z,now\ncargo:rustc-link-arg-tests=-lm\ncargo:warning=be careful\ncargo:rerun-if-changed=build.rs\ncargo:rerun-if-env-changed=CC";
+        let options = ParseOptions {
+            links: Some("foo".to_string()),
+            link_search_paths: PathRewrite {
+                rules: vec![PathRule {
+                    prefix: "/nix/store".into(),
+                    action: PathAction::Keep,
+                }],
+                ..PathRewrite::default()
+            },
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&output.output.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "cfgs": [{ "name": "fast_arithmetic", "value": "64" }],
+                "check_cfgs": ["cfg(has_foo)"],
+                "link_libs": [
+                    { "kind": "static", "modifiers": { "bundle": false }, "name": "foo", "rename": "bar" },
+                    { "kind": "dylib", "modifiers": {}, "name": "z", "rename": null },
+                ],
+                "link_search": [
+                    { "kind": "native", "path": "$out" },
+                    { "kind": "all", "path": "/nix/store/abc-zlib/lib" },
+                ],
+                "propagated_link_search": [
+                    { "kind": "native", "path": "/build/foo/lib" },
+                    { "kind": "all", "path": "/nix/store/abc-zlib/lib" },
+                ],
+                "rustc_flags": ["-L all=/nix/store/abc-zlib/lib -l dylib=z"],
+                "env": [{ "name": "GREETING", "value": "it's \"quoted\"" }],
+                "metadata": [{ "key": "root", "value": "/nix/store/abc-foo", "name": "DEP_FOO_ROOT" }],
+                "link_args": {
+                    "all": [],
+                    "cdylib": [],
+                    "bin": { "server": ["-Wl,-z,now"] },
+                    "bins": [],
+                    "tests": ["-lm"],
+                    "examples": [],
+                    "benches": [],
+                },
+                "warnings": [{ "line": 10, "message": "be careful" }],
+                "errors": [],
+                "ignored": [
+                    { "line": 11, "key": "rerun-if-changed", "value": "build.rs" },
+                    { "line": 12, "key": "rerun-if-env-changed", "value": "CC" },
+                ],
+            })
+        );
+
+        let out_dir = tempfile::tempdir().unwrap();
+        JsonWriter.write(&output, out_dir.path()).unwrap();
+        let written = fs::read_to_string(out_dir.path().join("build-script.json")).unwrap();
+        assert_eq!(written, output.output.to_json().unwrap());
+    }
+
+    #[test]
+    fn test_nix_output() {
+        assert_eq!(nix_string("plain"), "\"plain\"");
+        assert_eq!(
+            nix_string("it's \"${quoted}\" $out \\ \n"),
+            "\"it's \\\"\\${quoted}\\\" $out \\\\ \\n\""
+        );
+
+        let content = "cargo:rustc-cfg=fast_arithmetic=\"64\"\ncargo:rustc-check-cfg=cfg(has_foo)\ncargo:rustc-link-lib=static:-bundle=foo\ncargo:rustc-link-search=native=/build/foo/lib\ncargo:rustc-env=GREETING=${name} says \"hi\"\ncargo:rustc-env=GREETING=hello\ncargo:root=/nix/store/abc-foo\ncargo:rustc-link-arg-bin=my-server=-Wl,-z,now\ncargo:rustc-link-arg-tests=-lm";
+        let options = ParseOptions {
+            links: Some("foo".to_string()),
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        assert_eq!(
+            output.output.to_nix(),
+            r#"{
+  cfgs = [ "fast_arithmetic=\"64\"" ];
+  checkCfgs = [ "cfg(has_foo)" ];
+  linkLibs = [ "static:-bundle=foo" ];
+  linkSearch = [ "native=$out" ];
+  propagatedLinkSearch = [ "native=/build/foo/lib" ];
+  env = {
+    GREETING = "hello";
+  };
+  linkArgs = {
+    all = [ ];
+    cdylib = [ ];
+    bin = {
+      my-server = [ "-Wl,-z,now" ];
+    };
+    bins = [ ];
+    tests = [ "-lm" ];
+    examples = [ ];
+    benches = [ ];
+  };
+  depMetadata = {
+    DEP_FOO_ROOT = "/nix/store/abc-foo";
+  };
+}
+"#
+        );
+
+        let out_dir = tempfile::tempdir().unwrap();
+        NixWriter.write(&output, out_dir.path()).unwrap();
+        let written = fs::read_to_string(out_dir.path().join("build-script.nix")).unwrap();
+        assert_eq!(written, output.output.to_nix());
+    }
+
+    #[test]
+    fn test_argfiles() {
+        let content = "cargo:rustc-cfg=name=\"it's a value\"\ncargo:rustc-check-cfg=cfg(name, values(\"it's a value\"))\ncargo:rustc-link-lib=static=foo\ncargo:rustc-link-search=native=/nix/store/abc-foo/lib\ncargo:rustc-flags=-l z -L /build/zlib\ncargo:rustc-link-arg=-Wl,-rpath,$ORIGIN/../lib\ncargo:rustc-link-arg-bin=server=-Wl,-z,now";
+        let options = ParseOptions {
+            link_search_paths: PathRewrite {
+                rules: vec![PathRule {
+                    prefix: "/nix/store".into(),
+                    action: PathAction::Keep,
+                }],
+                ..PathRewrite::default()
+            },
+            ..ParseOptions::default()
+        };
+        let output = handle_content_with(content.to_string(), &options).unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        ArgfileWriter.write(&output, out_dir.path()).unwrap();
+        let read = |file_name: &str| fs::read_to_string(out_dir.path().join(file_name)).unwrap();
+        assert_eq!(
+            read("rustc-arguments.args"),
+            "--cfg\nname=\"it's a value\"\n--check-cfg\ncfg(name, values(\"it's a value\"))\n-l\nstatic=foo\n-l\nz\n"
+        );
+        // rustc does not expand the $out of the default replacement in an argument file
+        assert_eq!(
+            read("rustc-link-search-arguments"),
+            "-L 'native=/nix/store/abc-foo/lib' -L \"all=$out\""
+        );
+        assert_eq!(
+            read("rustc-propagated-arguments.args"),
+            "-L\nnative=/nix/store/abc-foo/lib\n-L\nall=/build/zlib\n"
+        );
+        assert_eq!(
+            read("rustc-link-arg.args"),
+            "-C\nlink-arg=-Wl,-rpath,$ORIGIN/../lib\n"
+        );
+        assert_eq!(
+            read("rustc-link-arg-bin/server.args"),
+            "-C\nlink-arg=-Wl,-z,now\n"
+        );
+        assert_eq!(read("rustc-link-arg-tests.args"), "");
+    }
+
+    #[test]
+    fn test_env_files() {
+        let content = "cargo:rustc-env=GREETING=it's \"$HOME\"\ncargo:rustc-env=EMPTY=\ncargo:root=/nix/store/abc-foo";
+        let output = handle_content_with(content.to_string(), &mycrate()).unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        EnvScriptWriter.write(&output, out_dir.path()).unwrap();
+        EnvNulWriter.write(&output, out_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("environment-variables.sh")).unwrap(),
+            "export GREETING='it'\\''s \"$HOME\"'\nexport EMPTY=''\nexport DEP_MYCRATE_ROOT='/nix/store/abc-foo'\n"
+        );
+        assert_eq!(
+            fs::read(out_dir.path().join("environment-variables.nul")).unwrap(),
+            b"GREETING=it's \"$HOME\"\0EMPTY=\0DEP_MYCRATE_ROOT=/nix/store/abc-foo\0"
+        );
+
+        let output = handle_content("cargo:rustc-env=NUL=a\0b".to_string()).unwrap();
+        assert!(EnvScriptWriter.write(&output, out_dir.path()).is_err());
+        assert!(EnvNulWriter.write(&output, out_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_output_writers() {
+        let names: Vec<&str> = output_writers()
+            .iter()
+            .map(|writer| writer.name())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["text", "json", "nix", "argfile", "env-sh", "env-nul"]
+        );
+        assert_eq!(output_writer("json").unwrap().name(), "json");
+        assert!(output_writer("yaml").is_none());
+
+        let mut temp = NamedTempFile::new().unwrap();
+        writeln!(
+            temp,
+            "cargo:rustc-cfg=foo\ncargo:rustc-env=FOO=bar\ncargo:warning=careful"
+        )
+        .unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        process_buildrs_output_with(
+            temp.path(),
+            out_dir.path(),
+            &ParseOptions::default(),
+            &["text", "json", "env-sh"].map(|name| output_writer(name).unwrap()),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let read = |file_name: &str| fs::read_to_string(out_dir.path().join(file_name)).unwrap();
+        assert_eq!(read("rustc-arguments"), "--cfg 'foo'");
+        assert!(read("build-script.json").contains("\"careful\""));
+        assert_eq!(read("environment-variables.sh"), "export FOO='bar'\n");
+        assert!(!out_dir.path().join("build-script.nix").exists());
+
+        // every writer is checked before the first one writes
+        let mut temp = NamedTempFile::new().unwrap();
+        writeln!(temp, "cargo:rustc-env=my.var=1").unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        let err = process_buildrs_output_with(
+            temp.path(),
+            out_dir.path(),
+            &ParseOptions::default(),
+            &["json", "text"].map(|name| output_writer(name).unwrap()),
+            &mut diagnostics,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("'my.var'"), "{err}");
+        assert_eq!(fs::read_dir(out_dir.path()).unwrap().count(), 0);
+
+        // a writer which can not write fails the run instead of panicking
+        let missing = out_dir.path().join("missing");
+        assert!(TextWriter.write(&TheResult::default(), &missing).is_err());
+    }
 }
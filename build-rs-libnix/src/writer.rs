@@ -0,0 +1,339 @@
+use crate::TheResult;
+use crate::quote::{is_shell_variable_name, shell_quote};
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+// writes a TheResult to files in the out dir, one implementation per --format
+pub trait OutputWriter {
+    // the value of --format
+    fn name(&self) -> &'static str;
+    // a one line description for --help
+    fn description(&self) -> &'static str;
+    // fails for a result the format can not hold, every writer of a run is checked before the
+    // first one writes, so a failing format leaves no files behind
+    fn check(&self, _out: &TheResult) -> Result<()> {
+        Ok(())
+    }
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()>;
+}
+
+// every output format, a new OutputWriter only has to be added here
+pub fn output_writers() -> Vec<Box<dyn OutputWriter>> {
+    vec![
+        Box::new(TextWriter),
+        Box::new(JsonWriter),
+        Box::new(NixWriter),
+        Box::new(ArgfileWriter),
+        Box::new(EnvScriptWriter),
+        Box::new(EnvNulWriter),
+    ]
+}
+
+pub fn output_writer(name: &str) -> Option<Box<dyn OutputWriter>> {
+    output_writers()
+        .into_iter()
+        .find(|writer| writer.name() == name)
+}
+
+fn write_file(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    std::fs::write(path, content)
+        .with_context(|| format!("Could not write file '{}'", path.display()))
+}
+
+// rustc-link-arg-bin/, the directory with one file per binary
+fn create_rustc_link_arg_bin_dir(out_dir: &Path) -> Result<PathBuf> {
+    let rustc_link_arg_bin_path = out_dir.join("rustc-link-arg-bin");
+    std::fs::create_dir_all(&rustc_link_arg_bin_path).with_context(|| {
+        format!(
+            "Could not create directory '{}'",
+            rustc_link_arg_bin_path.display()
+        )
+    })?;
+    Ok(rustc_link_arg_bin_path)
+}
+
+// rustc-env and links metadata names like my.var are fine for cargo, but not as NAME=VALUE in a shell
+fn check_shell_variable_names(out: &TheResult) -> Result<()> {
+    for (name, _) in environment(out) {
+        if !is_shell_variable_name(name) {
+            bail!(
+                "the environment variable name '{name}' can not be used in a shell, use the json, nix or env-nul format"
+            );
+        }
+    }
+    Ok(())
+}
+
+// rustc-arguments, rustc-propagated-arguments, environment-variables and the rustc-link-arg files
+// with shell quoted arguments, to be used like $(cat rustc-arguments)
+pub struct TextWriter;
+
+impl OutputWriter for TextWriter {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn description(&self) -> &'static str {
+        "rustc-arguments, environment-variables, ... with shell quoted arguments"
+    }
+
+    fn check(&self, out: &TheResult) -> Result<()> {
+        check_shell_variable_names(out)
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        self.check(out)?;
+        write_file(
+            &out_dir.join("rustc-arguments"),
+            out.rustc_arguments.join(" "),
+        )?;
+        write_file(
+            &out_dir.join("rustc-propagated-arguments"),
+            out.rustc_propagated_arguments.join(" "),
+        )?;
+        write_file(
+            &out_dir.join("environment-variables"),
+            out.environment_variables.join("\n"),
+        )?;
+
+        let rustc_link_args = [
+            ("rustc-link-arg", &out.rustc_link_arg),
+            ("rustc-link-arg-cdylib", &out.rustc_link_arg_cdylib),
+            ("rustc-link-arg-bins", &out.rustc_link_arg_bins),
+            ("rustc-link-arg-tests", &out.rustc_link_arg_tests),
+            ("rustc-link-arg-examples", &out.rustc_link_arg_examples),
+            ("rustc-link-arg-benches", &out.rustc_link_arg_benches),
+        ];
+        for (file_name, rustc_link_arg) in rustc_link_args {
+            write_file(&out_dir.join(file_name), rustc_link_arg.join(" "))?;
+        }
+
+        // one file per binary: rustc-link-arg-bin/<name>
+        let rustc_link_arg_bin_path = create_rustc_link_arg_bin_dir(out_dir)?;
+        for (bin, rustc_link_arg) in &out.rustc_link_arg_bin {
+            write_file(&rustc_link_arg_bin_path.join(bin), rustc_link_arg.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+// build-script.json, the whole result for tools which do not want to parse shell fragments
+pub struct JsonWriter;
+
+impl OutputWriter for JsonWriter {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn description(&self) -> &'static str {
+        "build-script.json with every directive, warnings and ignored directives"
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        write_file(&out_dir.join("build-script.json"), out.output.to_json()?)
+    }
+}
+
+// build-script.nix, an attribute set the nix code can import without any shell quoting
+pub struct NixWriter;
+
+impl OutputWriter for NixWriter {
+    fn name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn description(&self) -> &'static str {
+        "build-script.nix, an attribute set with cfgs, checkCfgs, linkLibs, env, ... to import"
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        write_file(&out_dir.join("build-script.nix"), out.output.to_nix())
+    }
+}
+
+// a rustc @argfile, one argument per line without any quoting
+fn argfile(args: &[String]) -> String {
+    args.iter().map(|arg| format!("{arg}\n")).collect()
+}
+
+// the arguments of rustc-arguments for an @argfile without -L, in the order of the
+// BuildScriptOutput fields
+fn rustc_args(out: &TheResult) -> Vec<String> {
+    let output = &out.output;
+    let mut args = vec![];
+    for cfg in &output.cfgs {
+        args.extend(["--cfg".to_string(), cfg.to_string()]);
+    }
+    for check_cfg in &output.check_cfgs {
+        args.extend(["--check-cfg".to_string(), check_cfg.clone()]);
+    }
+    for link_lib in &output.link_libs {
+        args.extend(["-l".to_string(), link_lib.to_string()]);
+    }
+    args
+}
+
+// the -L arguments of rustc-arguments, shell quoted as the replacement of a link search path
+// like $out is only known to the builder and rustc does not expand variables in an @argfile
+fn rustc_link_search_arguments(out: &TheResult) -> String {
+    let arguments: Vec<&str> = out
+        .rustc_arguments
+        .iter()
+        .filter(|argument| argument.starts_with("-L "))
+        .map(String::as_str)
+        .collect();
+    arguments.join(" ")
+}
+
+// the arguments of rustc-propagated-arguments for an @argfile, the paths as the build script
+// printed them
+fn rustc_propagated_args(out: &TheResult) -> Vec<String> {
+    out.output
+        .propagated_link_search
+        .iter()
+        .flat_map(|link_search| ["-L".to_string(), link_search.to_string()])
+        .collect()
+}
+
+// the arguments of rustc-link-arg and friends for an @argfile
+fn link_arg_args(flags: &[String]) -> Vec<String> {
+    flags
+        .iter()
+        .flat_map(|flag| ["-C".to_string(), format!("link-arg={flag}")])
+        .collect()
+}
+
+// the .args counterparts of rustc-arguments, rustc-propagated-arguments and the link arg files,
+// which are passed to rustc as @rustc-arguments.args, and rustc-link-search-arguments with the
+// -L arguments for the shell
+pub struct ArgfileWriter;
+
+impl OutputWriter for ArgfileWriter {
+    fn name(&self) -> &'static str {
+        "argfile"
+    }
+
+    fn description(&self) -> &'static str {
+        "rustc-arguments.args, rustc-link-arg.args, ... with one unquoted argument per line for rustc @FILE, and the shell quoted rustc-link-search-arguments"
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        let link_args = &out.output.link_args;
+        let argfiles = [
+            ("rustc-arguments.args", rustc_args(out)),
+            (
+                "rustc-propagated-arguments.args",
+                rustc_propagated_args(out),
+            ),
+            ("rustc-link-arg.args", link_arg_args(&link_args.all)),
+            (
+                "rustc-link-arg-cdylib.args",
+                link_arg_args(&link_args.cdylib),
+            ),
+            ("rustc-link-arg-bins.args", link_arg_args(&link_args.bins)),
+            ("rustc-link-arg-tests.args", link_arg_args(&link_args.tests)),
+            (
+                "rustc-link-arg-examples.args",
+                link_arg_args(&link_args.examples),
+            ),
+            (
+                "rustc-link-arg-benches.args",
+                link_arg_args(&link_args.benches),
+            ),
+        ];
+        for (file_name, args) in argfiles {
+            write_file(&out_dir.join(file_name), argfile(&args))?;
+        }
+        write_file(
+            &out_dir.join("rustc-link-search-arguments"),
+            rustc_link_search_arguments(out),
+        )?;
+
+        // one file per binary: rustc-link-arg-bin/<name>.args
+        let rustc_link_arg_bin_path = create_rustc_link_arg_bin_dir(out_dir)?;
+        for (bin, flags) in &link_args.bin {
+            write_file(
+                &rustc_link_arg_bin_path.join(format!("{bin}.args")),
+                argfile(&link_arg_args(flags)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// rustc-env followed by the links metadata, as NAME and VALUE
+fn environment(out: &TheResult) -> impl Iterator<Item = (&str, &str)> {
+    let env = out
+        .output
+        .env
+        .iter()
+        .map(|env| (env.name.as_str(), env.value.as_str()));
+    let metadata = out
+        .output
+        .metadata
+        .iter()
+        .map(|metadata| (metadata.name.as_str(), metadata.value.as_str()));
+    env.chain(metadata)
+}
+
+// an environment variable can not hold a NUL byte
+fn check_environment_values(out: &TheResult) -> Result<()> {
+    for (name, value) in environment(out) {
+        if value.contains('\0') {
+            bail!("the value of the environment variable '{name}' contains a NUL byte");
+        }
+    }
+    Ok(())
+}
+
+// environment-variables.sh, export NAME='VALUE' lines to source in the builder
+pub struct EnvScriptWriter;
+
+impl OutputWriter for EnvScriptWriter {
+    fn name(&self) -> &'static str {
+        "env-sh"
+    }
+
+    fn description(&self) -> &'static str {
+        "environment-variables.sh with 'export NAME=VALUE' lines to source"
+    }
+
+    fn check(&self, out: &TheResult) -> Result<()> {
+        check_environment_values(out)?;
+        check_shell_variable_names(out)
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        self.check(out)?;
+        let script: String = environment(out)
+            .map(|(name, value)| format!("export {}={}\n", name, shell_quote(value)))
+            .collect();
+        write_file(&out_dir.join("environment-variables.sh"), script)
+    }
+}
+
+// environment-variables.nul, NAME=VALUE entries ended by a NUL byte like `env -0` prints them,
+// for names like my.var which can not be used in a shell as well
+pub struct EnvNulWriter;
+
+impl OutputWriter for EnvNulWriter {
+    fn name(&self) -> &'static str {
+        "env-nul"
+    }
+
+    fn description(&self) -> &'static str {
+        "environment-variables.nul with NUL separated NAME=VALUE entries, for any name"
+    }
+
+    fn check(&self, out: &TheResult) -> Result<()> {
+        check_environment_values(out)
+    }
+
+    fn write(&self, out: &TheResult, out_dir: &Path) -> Result<()> {
+        self.check(out)?;
+        let nul: String = environment(out)
+            .map(|(name, value)| format!("{name}={value}\0"))
+            .collect();
+        write_file(&out_dir.join("environment-variables.nul"), nul)
+    }
+}
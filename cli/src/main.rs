@@ -1,15 +1,18 @@
-use anyhow::Result;
-use build_rs_libnix::handle_content;
+use build_rs_libnix::{
+    ColorChoice, Diagnostic, DiagnosticSink, Level, Lint, OutputWriter, ParseError, ParseOptions,
+    PathAction, PathRewrite, PathRule, Policy, Relocation, Severity, TerminalRenderer,
+    output_writer, output_writers, process_buildrs_output_with,
+};
 use clap::Parser;
-use std::fs;
-use std::path::{Path, PathBuf};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(clap::Parser, Debug)]
 #[clap(
     name = "build-rs-libnix",
     about = "Parse the output of a build.rs script for 'nix build'"
 )]
-
 pub struct BuildRsNixArgs {
     /// Absolute path to the /nix/store/...-build-script-build.out file to parse
     #[clap(long = "script-output", value_name = "PATH")]
@@ -18,47 +21,239 @@ pub struct BuildRsNixArgs {
     /// A directory where the nix/* files are generated to
     #[clap(long = "out-dir", value_name = "PATH")]
     pub out_dir: PathBuf,
+
+    /// The `links` key of the package, defaults to $CARGO_MANIFEST_LINKS. Needed for links metadata (DEP_<LINKS>_<KEY>)
+    #[clap(long = "links", value_name = "NAME")]
+    pub links: Option<String>,
+
+    /// Name of a binary target of the crate, can be repeated. If given, 'rustc-link-arg-bin' must name one of them
+    #[clap(long = "bin", value_name = "NAME")]
+    pub bin_names: Vec<String>,
+
+    /// Do not fail when the build script emits 'cargo:error', for debugging
+    #[clap(long = "allow-script-errors")]
+    pub allow_script_errors: bool,
+
+    /// Go on after a malformed directive and report every error of the output at once
+    #[clap(long = "collect-errors")]
+    pub collect_errors: bool,
+
+    /// Process these directives silently, can be repeated
+    #[clap(long = "allow", value_name = "LINT")]
+    pub allow: Vec<LintArg>,
+
+    /// Process these directives, but print a warning, can be repeated
+    #[clap(long = "warn", value_name = "LINT")]
+    pub warn: Vec<LintArg>,
+
+    /// Fail on these directives, can be repeated. Applied after --allow and --warn
+    #[clap(long = "deny", value_name = "LINT")]
+    pub deny: Vec<LintArg>,
+
+    /// Pass rustc-link-search paths below PREFIX to rustc as they are, e.g. /nix/store. Can be repeated
+    #[clap(long = "keep-link-search", value_name = "PREFIX")]
+    pub keep_link_search: Vec<PathBuf>,
+
+    /// Replace PREFIX of rustc-link-search paths, e.g. /build/source/target/out='$out/out'. Can be repeated
+    #[clap(long = "map-link-search", value_name = "PREFIX=REPLACEMENT", value_parser = parse_map_link_search)]
+    pub map_link_search: Vec<PathRule>,
+
+    /// Fail on rustc-link-search paths below PREFIX. Can be repeated
+    #[clap(long = "deny-link-search", value_name = "PREFIX")]
+    pub deny_link_search: Vec<PathBuf>,
+
+    /// What happens to rustc-link-search paths no other rule matches: 'keep', 'error' or a replacement of the whole path
+    #[clap(
+        long = "link-search-fallback",
+        value_name = "ACTION",
+        default_value = "$out"
+    )]
+    pub link_search_fallback: String,
+
+    /// The OUT_DIR the build script ran with, replaced by --build-out-dir-placeholder in rustc-env, metadata, link args and cfgs
+    #[clap(long = "build-out-dir", value_name = "PATH", value_parser = parse_prefix)]
+    pub build_out_dir: Option<String>,
+
+    /// What --build-out-dir is replaced with
+    #[clap(
+        long = "build-out-dir-placeholder",
+        value_name = "PLACEHOLDER",
+        default_value = "@BUILD_OUT_DIR@"
+    )]
+    pub build_out_dir_placeholder: String,
+
+    /// Replace another path prefix like --build-out-dir, e.g. /build=@BUILD@. Can be repeated
+    #[clap(long = "relocate", value_name = "PREFIX=PLACEHOLDER", value_parser = parse_relocate)]
+    pub relocate: Vec<Relocation>,
+
+    /// Whether warnings and errors are colored, 'auto' honors NO_COLOR
+    #[clap(long = "color", value_name = "WHEN", default_value = "auto")]
+    pub color: Color,
+
+    /// The files written to --out-dir, can be repeated
+    #[clap(
+        long = "format",
+        value_name = "FORMAT",
+        default_value = "text",
+        value_parser = format_parser()
+    )]
+    pub formats: Vec<String>,
+}
+
+fn parse_map_link_search(value: &str) -> Result<PathRule, String> {
+    match value.split_once('=') {
+        Some((prefix, replacement)) if !prefix.is_empty() => Ok(PathRule {
+            prefix: prefix.into(),
+            action: PathAction::Replace(replacement.to_string()),
+        }),
+        _ => Err("expected PREFIX=REPLACEMENT".to_string()),
+    }
+}
+
+// a path prefix to relocate, / would match every path
+fn parse_prefix(value: &str) -> Result<String, String> {
+    if value.trim_end_matches('/').is_empty() {
+        return Err("expected a non-empty path prefix other than '/'".to_string());
+    }
+    Ok(value.to_string())
 }
 
-fn main() -> Result<()> {
+fn parse_relocate(value: &str) -> Result<Relocation, String> {
+    match value.split_once('=') {
+        Some((prefix, placeholder)) => Ok(Relocation {
+            prefix: parse_prefix(prefix)?,
+            placeholder: placeholder.to_string(),
+        }),
+        _ => Err("expected PREFIX=PLACEHOLDER".to_string()),
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum LintArg {
+    /// cargo::KEY=VALUE with a KEY cargo does not know (denied by default)
+    Unknown,
+    /// cargo:rerun-if-changed, cargo:rerun-if-env-changed and friends (allowed by default)
+    Rerun,
+}
+
+impl From<LintArg> for Lint {
+    fn from(lint: LintArg) -> Self {
+        match lint {
+            LintArg::Unknown => Lint::Unknown,
+            LintArg::Rerun => Lint::Rerun,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<Color> for ColorChoice {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Auto => ColorChoice::Auto,
+            Color::Always => ColorChoice::Always,
+            Color::Never => ColorChoice::Never,
+        }
+    }
+}
+
+// the names of the library's output writers
+fn format_parser() -> PossibleValuesParser {
+    PossibleValuesParser::new(
+        output_writers()
+            .iter()
+            .map(|writer| PossibleValue::new(writer.name()).help(writer.description())),
+    )
+}
+
+fn main() -> ExitCode {
     let args = BuildRsNixArgs::parse();
-    let input = fs::read_to_string(Path::new(&args.script_output)).expect("Could not read file");
-    match handle_content(input) {
-        Ok(out) => {
-            let out_dir = args.out_dir;
-
-            let rustc_arguments_path = Path::new(&out_dir).join("rustc-arguments");
-            std::fs::write(rustc_arguments_path, out.rustc_arguments.join(" "))
-                .expect("Unable to write data to file");
-
-            let rustc_propagated_arguments_path =
-                Path::new(&out_dir).join("rustc-propagated-arguments");
-            std::fs::write(
-                rustc_propagated_arguments_path,
-                out.rustc_propagated_arguments.join(" "),
-            )
-            .expect("Unable to write data to file");
-
-            let environment_variables_path = Path::new(&out_dir).join("environment-variables");
-            std::fs::write(
-                environment_variables_path,
-                out.environment_variables.join("\n"),
-            )
-            .expect("Unable to write data to file");
-
-            let rustc_link_arg_benches_path = Path::new(&out_dir).join("rustc-link-arg-benches");
-            std::fs::write(
-                rustc_link_arg_benches_path,
-                out.rustc_link_arg_benches.join(" "),
-            )
-            .expect("Unable to write data to file");
-
-            println!(
-                "build.rs related nix files written to '{}'",
-                out_dir.display()
-            );
+    let mut policy = Policy::default();
+    for (lints, level) in [
+        (&args.allow, Level::Allow),
+        (&args.warn, Level::Warn),
+        (&args.deny, Level::Deny),
+    ] {
+        for lint in lints {
+            policy.set((*lint).into(), level);
+        }
+    }
+    let mut link_search_paths = PathRewrite {
+        rules: args.map_link_search,
+        fallback: match args.link_search_fallback.as_str() {
+            "keep" => PathAction::Keep,
+            "error" => PathAction::Error,
+            replacement => PathAction::Replace(replacement.to_string()),
+        },
+    };
+    for (prefixes, action) in [
+        (args.keep_link_search, PathAction::Keep),
+        (args.deny_link_search, PathAction::Error),
+    ] {
+        link_search_paths
+            .rules
+            .extend(prefixes.into_iter().map(|prefix| PathRule {
+                prefix,
+                action: action.clone(),
+            }));
+    }
+    let mut relocations = args.relocate;
+    if let Some(build_out_dir) = args.build_out_dir {
+        relocations.push(Relocation {
+            prefix: build_out_dir,
+            placeholder: args.build_out_dir_placeholder,
+        });
+    }
+    let options = ParseOptions {
+        links: args
+            .links
+            .or_else(|| std::env::var("CARGO_MANIFEST_LINKS").ok()),
+        bin_names: (!args.bin_names.is_empty()).then_some(args.bin_names),
+        allow_script_errors: args.allow_script_errors,
+        collect_errors: args.collect_errors,
+        policy,
+        link_search_paths,
+        relocations,
+    };
+    let mut renderer = TerminalRenderer {
+        file_name: Some(args.script_output.display().to_string()),
+        color: args.color.into(),
+    };
+    // --format only takes the names of output_writers()
+    let writers: Vec<Box<dyn OutputWriter>> = args
+        .formats
+        .iter()
+        .filter_map(|format| output_writer(format))
+        .collect();
+    match process_buildrs_output_with(
+        &args.script_output,
+        &args.out_dir,
+        &options,
+        &writers,
+        &mut renderer,
+    ) {
+        Ok(()) => ExitCode::SUCCESS,
+        // the renderer has already printed every parse error, --collect-errors ends with a summary
+        Err(e) if e.is::<ParseError>() => {
+            if let Some(ParseError::Multiple(errors)) = e.downcast_ref() {
+                renderer.report(Diagnostic {
+                    severity: Severity::Error,
+                    message: format!("{} error(s), no files written", errors.len()),
+                    line_number: None,
+                    line: None,
+                    context: vec![],
+                });
+            }
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::FAILURE
         }
-        Err(e) => return Err(e),
     }
-    Ok(())
 }